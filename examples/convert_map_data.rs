@@ -0,0 +1,28 @@
+//! Converts the generated `.npy` chunks under `MapData` to the compact format read by
+//! `ChunkFormat::Packed`, and reports the space saved.
+//!
+//! Run with `cargo run --release --example convert_map_data [--reset]`.
+
+use rs3_pathfinding::{chunk_format, data_paths::DataPaths, util::{CHUNK_SIZE, FLOORS, RS_HEIGHT, RS_LENGTH}};
+use std::{error::Error,
+          fs,
+          path::Path};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let paths = DataPaths::default();
+    let reset = std::env::args().any(|arg| arg == "--reset");
+    let written = chunk_format::convert(&paths, reset)?;
+    let size = |path: &Path| fs::metadata(path).map_or(0, |metadata| metadata.len());
+    let (mut npy, mut packed) = (0, 0);
+    for i in 0..RS_LENGTH / CHUNK_SIZE {
+        for j in 0..RS_HEIGHT / CHUNK_SIZE {
+            for k in 0..FLOORS {
+                npy += size(&paths.walk(i, j, k)) + size(&paths.bd(i, j, k)) + size(&paths.se(i, j, k));
+                packed += size(&paths.packed(i, j, k));
+            }
+        }
+    }
+    println!("packed {written} chunks");
+    println!("npy: {} MiB, packed: {} MiB", npy >> 20, packed >> 20);
+    Ok(())
+}
@@ -0,0 +1,73 @@
+//! Times building a `MapSection` and querying its walk and Bladed Dive ranges, next to the per-tile
+//! `HashMap<(u16, u16), Vec<_>>` layout `MapSection` used before the flat offset arrays.
+//!
+//! Run with `cargo run --release --example map_section_bench`. Generates the map data first if needed.
+
+use rs3_pathfinding::{data_paths::DataPaths, map_section::{MapSection, MapView}, preprocessing};
+use std::{collections::HashMap,
+          error::Error,
+          hint::black_box,
+          time::Instant};
+
+const CENTRE: (u16, u16) = (2130, 5154);
+const RADIUS: usize = 120;
+const BUILDS: u32 = 10;
+const QUERY_PASSES: u32 = 20;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let paths = DataPaths::default();
+    preprocessing::setup(false, &paths);
+
+    let now = Instant::now();
+    let mut section = None;
+    for _ in 0..BUILDS {
+        section = Some(black_box(MapSection::around(&[CENTRE], RADIUS, 0, &paths)?));
+    }
+    let section = section.unwrap();
+    println!("build: {:.2?} per {side}x{side} section", now.elapsed() / BUILDS, side = 2 * RADIUS + 1);
+
+    let (x_range, y_range) = (CENTRE.0 - RADIUS as u16..=CENTRE.0 + RADIUS as u16, CENTRE.1 - RADIUS as u16..=CENTRE.1 + RADIUS as u16);
+    let now = Instant::now();
+    let mut old_layout = None;
+    for _ in 0..BUILDS {
+        let mut walk_map = HashMap::new();
+        let mut bd_map = HashMap::new();
+        for x in x_range.clone() {
+            for y in y_range.clone() {
+                walk_map.insert((x, y), section.walk_range(x, y, 0).to_vec());
+                bd_map.insert((x, y), section.bd_range(x, y, 0).to_vec());
+            }
+        }
+        old_layout = Some(black_box((walk_map, bd_map)));
+    }
+    let (walk_map, bd_map) = old_layout.unwrap();
+    println!("build (HashMap layout): {:.2?} extra per section to fill the maps", now.elapsed() / BUILDS);
+
+    let queries = QUERY_PASSES as usize * x_range.len() * y_range.len();
+    let now = Instant::now();
+    let mut total = 0;
+    for _ in 0..QUERY_PASSES {
+        for x in x_range.clone() {
+            for y in y_range.clone() {
+                total += black_box(section.walk_range(x, y, 0)).len();
+                total += black_box(section.bd_range(x, y, 0)).len();
+            }
+        }
+    }
+    let elapsed = now.elapsed();
+    println!("query: {:.1} ns per walk + bd lookup ({total} tiles)", elapsed.as_nanos() as f64 / queries as f64);
+
+    let now = Instant::now();
+    let mut total = 0;
+    for _ in 0..QUERY_PASSES {
+        for x in x_range.clone() {
+            for y in y_range.clone() {
+                total += black_box(&walk_map[&(x, y)]).len();
+                total += black_box(&bd_map[&(x, y)]).len();
+            }
+        }
+    }
+    let elapsed = now.elapsed();
+    println!("query (HashMap layout): {:.1} ns per walk + bd lookup ({total} tiles)", elapsed.as_nanos() as f64 / queries as f64);
+    Ok(())
+}
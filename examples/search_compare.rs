@@ -0,0 +1,74 @@
+//! Checks the packed radix-heap search against the generic reference search on random routes, and times
+//! both.
+//!
+//! Run with `cargo run --release --example search_compare`. Generates the map data first if needed.
+
+use rs3_pathfinding::{action, data_paths::DataPaths, goal::Goal, loadout::Loadout, map_section::{MapSection, MapView},
+                      pathfinding::{self, Heuristic}, preprocessing, state::State, transport::Player};
+use std::{error::Error,
+          time::{Duration, Instant}};
+
+const CENTRE: (u16, u16) = (2130, 5154);
+const RADIUS: u16 = 60;
+const ROUTES: usize = 200;
+
+/// Small xorshift generator so the routes are the same on every run.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self, bound: u16) -> u16 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as u16
+    }
+
+    fn tile(&mut self) -> (u16, u16) {
+        (CENTRE.0 - RADIUS + self.next(2 * RADIUS + 1), CENTRE.1 - RADIUS + self.next(2 * RADIUS + 1))
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let paths = DataPaths::default();
+    preprocessing::setup(false, &paths);
+    let section = MapSection::around(&[CENTRE], RADIUS as usize + 20, 0, &paths)?;
+    let loadout = Loadout::default();
+    let heuristic = Heuristic::new(&paths, &loadout)?;
+    let player = Player::default();
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    let (mut packed_time, mut reference_time) = (Duration::ZERO, Duration::ZERO);
+    let (mut routes, mut mismatches) = (0, 0);
+    while routes < ROUTES {
+        let (x, y) = rng.tile();
+        if section.walk_range(x, y, 0).is_empty() {
+            continue;
+        }
+        let start = State { pos_x: x, pos_y: y, floor: 0, direction: rng.next(8) as u8, secd: 0, scd: 0, ecd: 0, bdcd: 0, bgcd: 0 };
+        let goal = Goal::near(rng.tile().0, rng.tile().1);
+        let now = Instant::now();
+        let packed = pathfinding::a_star_end_buffer(start.clone(), &goal, &section, &heuristic, &loadout, &player);
+        packed_time += now.elapsed();
+        let now = Instant::now();
+        let reference = pathfinding::a_star_reference(start.clone(), std::slice::from_ref(&goal), &section, &heuristic, &loadout, &player);
+        reference_time += now.elapsed();
+        routes += 1;
+        let agrees = match (&packed, &reference) {
+            (Ok(packed), Ok(reference)) => {
+                packed.ticks == reference.ticks && packed.path.first() == Some(&start)
+                    && action::action_plan(&packed.path, &section, &loadout).is_some_and(|plan| plan.len() == packed.ticks)
+            }
+            (Err(packed), Err(reference)) => packed == reference,
+            _ => false,
+        };
+        if !agrees {
+            mismatches += 1;
+            println!("mismatch from {start:?} to {goal:?}: packed {packed:?}, reference {reference:?}");
+        }
+    }
+    println!("{routes} routes, {mismatches} mismatches");
+    println!("packed: {:.2?} per route, reference: {:.2?} per route", packed_time / ROUTES as u32, reference_time / ROUTES as u32);
+    if mismatches > 0 {
+        return Err(format!("{mismatches} routes differ from the reference search").into());
+    }
+    Ok(())
+}
@@ -0,0 +1,106 @@
+//! Times the route from `main.rs` and reports how many nodes the search expands per second, then times
+//! generating successors into one reused buffer against the old way of allocating a new `Vec` for every
+//! expansion.
+//!
+//! Run with `cargo run --release --example successor_bench`. Generates the map data first if needed.
+
+use rs3_pathfinding::{data_paths::DataPaths, goal::Goal, loadout::Loadout, map_section::{MapSection, MapView},
+                      pathfinding::{self, Heuristic}, preprocessing, state::State,
+                      transport::{Player, Teleport, Transport}};
+use std::{cell::Cell,
+          error::Error,
+          hint::black_box,
+          time::Instant};
+
+const RUNS: u32 = 20;
+
+/// Counts expansions: the search asks for the walk range exactly once per node it expands.
+struct Counting<'a> {
+    map: &'a MapSection,
+    expanded: Cell<usize>,
+}
+
+impl MapView for Counting<'_> {
+    fn is_loaded(&self, x: u16, y: u16, floor: u8) -> bool {
+        self.map.is_loaded(x, y, floor)
+    }
+
+    fn walk_range(&self, x: u16, y: u16, floor: u8) -> &[(u16, u16, u8)] {
+        self.expanded.set(self.expanded.get() + 1);
+        self.map.walk_range(x, y, floor)
+    }
+
+    fn bd_range(&self, x: u16, y: u16, floor: u8) -> &[(u16, u16, u8)] {
+        self.map.bd_range(x, y, floor)
+    }
+
+    fn surge_range(&self, x: u16, y: u16, floor: u8, direction: u8) -> (usize, usize) {
+        self.map.surge_range(x, y, floor, direction)
+    }
+
+    fn escape_range(&self, x: u16, y: u16, floor: u8, direction: u8) -> (usize, usize) {
+        self.map.escape_range(x, y, floor, direction)
+    }
+
+    fn transports(&self, x: u16, y: u16, floor: u8) -> &[Transport] {
+        self.map.transports(x, y, floor)
+    }
+
+    fn all_transports(&self) -> impl Iterator<Item = &Transport> {
+        self.map.all_transports()
+    }
+
+    fn teleports(&self) -> &[Teleport] {
+        self.map.teleports()
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let paths = DataPaths::default();
+    preprocessing::setup(false, &paths);
+    let start = State { pos_x: 2125, pos_y: 5146, floor: 0, direction: 4, secd: 0, scd: 0, ecd: 0, bdcd: 0, bgcd: 0 };
+    let end = (2134, 5162);
+    let section = MapSection::around(&[(start.pos_x, start.pos_y), end], 120, 0, &paths)?;
+    let map = Counting { map: &section, expanded: Cell::new(0) };
+    let loadout = Loadout::default();
+    let heuristic = Heuristic::new(&paths, &loadout)?;
+    let player = Player::default();
+    let now = Instant::now();
+    for _ in 0..RUNS {
+        pathfinding::a_star_end_buffer(start.clone(), &Goal::near(end.0, end.1), &map, &heuristic, &loadout, &player)?;
+    }
+    let elapsed = now.elapsed();
+    let expanded = map.expanded.get();
+    println!("{:.2?} per search, {} nodes expanded per search", elapsed / RUNS, expanded / RUNS as usize);
+    println!("{:.0} nodes/s", expanded as f64 / elapsed.as_secs_f64());
+
+    let goals = [Goal::near(end.0, end.1)];
+    let start = &start;
+    let states: Vec<State> = (start.pos_x - 20..=start.pos_x + 20)
+        .flat_map(|x| (start.pos_y - 20..=start.pos_y + 20).map(move |y| (x, y)))
+        .filter(|&(x, y)| !section.walk_range(x, y, 0).is_empty())
+        .flat_map(|(x, y)| (0..8).map(move |direction| State { pos_x: x, pos_y: y, direction, ..start.clone() }))
+        .collect();
+    let now = Instant::now();
+    let mut adjacent = Vec::new();
+    for _ in 0..RUNS {
+        for state in &states {
+            pathfinding::successors(state, &section, &loadout, &player, &goals, &mut adjacent);
+            black_box(&adjacent);
+        }
+    }
+    let reused = now.elapsed();
+    let now = Instant::now();
+    for _ in 0..RUNS {
+        for state in &states {
+            let mut adjacent = Vec::new();
+            pathfinding::successors(state, &section, &loadout, &player, &goals, &mut adjacent);
+            black_box(adjacent);
+        }
+    }
+    let allocating = now.elapsed();
+    let calls = RUNS as f64 * states.len() as f64;
+    println!("successors: {:.0} ns per call into a reused buffer, {:.0} ns allocating a new Vec per call",
+             reused.as_nanos() as f64 / calls, allocating.as_nanos() as f64 / calls);
+    Ok(())
+}
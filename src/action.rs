@@ -0,0 +1,175 @@
+use std::cmp::max;
+use crate::{loadout::Loadout,
+            map_section::MapView,
+            state::State};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Walk { x: u16, y: u16 },
+    Run { x: u16, y: u16 },
+    Surge,
+    Escape,
+    BladedDive { x: u16, y: u16 },
+    Barge { x: u16, y: u16 },
+    /// Use a staircase, ladder or other transport starting on the current tile, arriving at the given tile.
+    Transport { x: u16, y: u16, floor: u8, ticks: usize },
+    Teleport { x: u16, y: u16, floor: u8, ticks: usize },
+    Wait,
+}
+
+impl Action {
+    /// Number of ticks the action takes. Abilities take none and share a tick with the next action.
+    pub fn ticks(&self) -> usize {
+        match self {
+            Action::Walk { .. } | Action::Run { .. } | Action::Wait => 1,
+            Action::Transport { ticks, .. } | Action::Teleport { ticks, .. } => *ticks,
+            Action::Surge | Action::Escape | Action::BladedDive { .. } | Action::Barge { .. } => 0,
+        }
+    }
+}
+
+/// Works out which transition took `from` to `to` by replaying each candidate move.
+pub fn classify(from: &State, to: &State, map: &impl MapView, loadout: &Loadout) -> Option<Action> {
+    let (abilities, cooldowns) = (&loadout.abilities, &loadout.cooldowns);
+    if from.update() == *to {
+        return Some(Action::Wait);
+    }
+    if abilities.bladed_dive && from.can_bd() && from.bd(to.pos_x, to.pos_y, to.direction, cooldowns) == *to {
+        return Some(Action::BladedDive { x: to.pos_x, y: to.pos_y });
+    }
+    if abilities.barge && from.can_barge() && from.barge(to.pos_x, to.pos_y, to.direction, cooldowns) == *to {
+        return Some(Action::Barge { x: to.pos_x, y: to.pos_y });
+    }
+    if abilities.surge && from.can_surge(cooldowns) && from.surge(map, cooldowns) == *to {
+        return Some(Action::Surge);
+    }
+    if abilities.escape && from.can_escape(cooldowns) && from.escape(map, cooldowns) == *to {
+        return Some(Action::Escape);
+    }
+    for transport in map.transports(from.pos_x, from.pos_y, from.floor) {
+        if from.transport(transport) == *to {
+            return Some(Action::Transport { x: to.pos_x, y: to.pos_y, floor: to.floor, ticks: transport.ticks });
+        }
+    }
+    for teleport in map.teleports() {
+        if from.teleport(teleport) == *to {
+            return Some(Action::Teleport { x: to.pos_x, y: to.pos_y, floor: to.floor, ticks: teleport.ticks });
+        }
+    }
+    let walkable = map.walk_range(from.pos_x, from.pos_y, from.floor).contains(&(to.pos_x, to.pos_y, to.direction));
+    if walkable && from.r#move(to.pos_x, to.pos_y, to.direction).update() == *to {
+        let distance = max(from.pos_x.abs_diff(to.pos_x), from.pos_y.abs_diff(to.pos_y));
+        return Some(if distance <= 1 {
+            Action::Walk { x: to.pos_x, y: to.pos_y }
+        } else {
+            Action::Run { x: to.pos_x, y: to.pos_y }
+        });
+    }
+    None
+}
+
+/// Turns a path into the actions taken on each tick. Abilities come first within a tick, followed by the
+/// walk, run, wait, transport or teleport that ends it; the remaining ticks of longer ones are left empty.
+/// Returns `None` if two consecutive states are not connected by any move.
+pub fn action_plan(path: &[State], map: &impl MapView, loadout: &Loadout) -> Option<Vec<Vec<Action>>> {
+    let mut ticks = Vec::new();
+    let mut current = Vec::new();
+    for pair in path.windows(2) {
+        let action = classify(&pair[0], &pair[1], map, loadout)?;
+        current.push(action);
+        if action.ticks() > 0 {
+            ticks.push(std::mem::take(&mut current));
+            ticks.extend((1..action.ticks()).map(|_| Vec::new()));
+        }
+    }
+    if !current.is_empty() {
+        ticks.push(current);
+    }
+    Some(ticks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{loadout::AbilitySet,
+                test_map::GridMap,
+                transport::{Teleport, Transport}};
+
+    fn state_at(x: u16, y: u16, direction: u8) -> State {
+        State { pos_x: x, pos_y: y, floor: 0, direction, secd: 0, scd: 0, ecd: 0, bdcd: 0, bgcd: 0 }
+    }
+
+    #[test]
+    fn steps_of_one_tile_are_walks_and_longer_ones_runs() {
+        let map = GridMap::new(20, 20, 1, &[]);
+        let loadout = Loadout::default();
+        let from = state_at(5, 5, 0);
+        assert_eq!(classify(&from, &from.update().r#move(6, 5, 2), &map, &loadout), Some(Action::Walk { x: 6, y: 5 }));
+        assert_eq!(classify(&from, &from.update().r#move(7, 6, 1), &map, &loadout), Some(Action::Run { x: 7, y: 6 }));
+    }
+
+    #[test]
+    fn surge_and_escape_are_told_apart_by_where_they_land() {
+        let map = GridMap::new(20, 20, 1, &[]);
+        let loadout = Loadout::default();
+        let from = state_at(10, 5, 2);
+        let surged = from.surge(&map, &loadout.cooldowns);
+        let escaped = from.escape(&map, &loadout.cooldowns);
+        assert_eq!((surged.pos_x, escaped.pos_x), (19, 3));
+        assert_eq!(classify(&from, &surged, &map, &loadout), Some(Action::Surge));
+        assert_eq!(classify(&from, &escaped, &map, &loadout), Some(Action::Escape));
+    }
+
+    #[test]
+    fn bladed_dive_and_barge_only_count_when_the_loadout_has_them() {
+        let map = GridMap::new(20, 20, 1, &[]);
+        let with_barge = Loadout::new(AbilitySet::ALL, Loadout::default().cooldowns);
+        let without_barge = Loadout::default();
+        let from = state_at(5, 5, 0);
+        let dived = from.bd(9, 9, 1, &with_barge.cooldowns);
+        let barged = from.barge(12, 5, 2, &with_barge.cooldowns);
+        assert_eq!(classify(&from, &dived, &map, &with_barge), Some(Action::BladedDive { x: 9, y: 9 }));
+        assert_eq!(classify(&from, &barged, &map, &with_barge), Some(Action::Barge { x: 12, y: 5 }));
+        assert_eq!(classify(&from, &barged, &map, &without_barge), None);
+    }
+
+    #[test]
+    fn waiting_lets_cooldowns_tick_down() {
+        let map = GridMap::new(20, 20, 1, &[]);
+        let loadout = Loadout::default();
+        let surged = state_at(5, 5, 0).surge(&map, &loadout.cooldowns);
+        assert_eq!(classify(&surged, &surged.update(), &map, &loadout), Some(Action::Wait));
+    }
+
+    #[test]
+    fn plans_pad_long_actions_and_put_abilities_before_the_tick_they_share() {
+        let [up, _] = Transport::connector((1, 5, 0), (1, 5, 1), 3);
+        let home = Teleport::new("Home", (2, 2, 0), 2);
+        let map = GridMap::new(20, 20, 2, &[]).with_transports(vec![up.clone()]).with_teleports(vec![home.clone()]);
+        let loadout = Loadout::default();
+        let start = state_at(10, 5, 6);
+        let surged = start.surge(&map, &loadout.cooldowns);
+        let walked = surged.update().r#move(1, 5, 2);
+        let climbed = walked.transport(&up);
+        let teleported = climbed.teleport(&home);
+        let plan = action_plan(&[start, surged, walked, climbed, teleported], &map, &loadout).unwrap();
+        assert_eq!(plan, [
+            vec![Action::Surge, Action::Walk { x: 1, y: 5 }],
+            vec![Action::Transport { x: 1, y: 5, floor: 1, ticks: 3 }],
+            vec![],
+            vec![],
+            vec![Action::Teleport { x: 2, y: 2, floor: 0, ticks: 2 }],
+            vec![],
+        ]);
+    }
+
+    #[test]
+    fn states_no_single_action_connects_have_no_plan() {
+        let map = GridMap::new(20, 20, 1, &[]);
+        let loadout = Loadout::default();
+        let from = state_at(5, 5, 0);
+        let far = from.update().r#move(15, 15, 1);
+        assert_eq!(classify(&from, &far, &map, &loadout), None);
+        assert_eq!(action_plan(&[from.clone(), from.update(), far], &map, &loadout), None);
+    }
+}
@@ -0,0 +1,352 @@
+use memmap2::Mmap;
+use ndarray::{s, Array3, ArrayView3, Ix3};
+use ndarray_npy::{ReadableElement, ViewElement, ViewNpyExt};
+use std::{collections::HashMap,
+          fs::File,
+          path::Path,
+          sync::{Arc, Mutex}};
+use crate::{chunk_format,
+            data_paths::DataPaths,
+            error::{read_array, view_array, Error, Result},
+            map_section::FloorData,
+            util::CHUNK_SIZE};
+
+/// Side length of the square pages `ChunkCache::page` decodes. Divides `CHUNK_SIZE`, so a page never
+/// spans two chunks.
+pub(crate) const PAGE_SIZE: usize = 64;
+
+/// One of a chunk's arrays, either read into memory or mapped from its file.
+#[derive(Debug)]
+enum Storage<A> {
+    Owned(Array3<A>),
+    Mapped(Mmap),
+}
+
+impl<A: ReadableElement + ViewElement> Storage<A> {
+    fn read(path: &Path, depth: usize) -> Result<Storage<A>> {
+        read_array(path, &shape(depth)).map(Storage::Owned)
+    }
+
+    /// Maps `path`, checking its header up front so `view` cannot fail later.
+    fn map(path: &Path, depth: usize) -> Result<Storage<A>> {
+        let file = File::open(path).map_err(|err| Error::io(path, err))?;
+        // Safety: the generated files are only ever replaced by `setup`, which must not run while maps
+        // of them are alive.
+        let map = unsafe { Mmap::map(&file) }.map_err(|err| Error::io(path, err))?;
+        view_array::<A, Ix3>(path, &map, &shape(depth))?;
+        Ok(Storage::Mapped(map))
+    }
+
+    fn view(&self) -> ArrayView3<'_, A> {
+        match self {
+            Storage::Owned(arr) => arr.view(),
+            Storage::Mapped(map) => ArrayView3::view_npy(map).expect("header checked when mapped"),
+        }
+    }
+
+    fn bytes(&self) -> usize {
+        match self {
+            Storage::Owned(arr) => arr.len() * std::mem::size_of::<A>(),
+            Storage::Mapped(map) => map.len(),
+        }
+    }
+}
+
+fn shape(depth: usize) -> [Option<usize>; 3] {
+    [Some(CHUNK_SIZE), Some(CHUNK_SIZE), Some(depth)]
+}
+
+/// The generated walk, Bladed Dive and Surge/Escape data for one chunk of one floor.
+#[derive(Debug)]
+pub struct Chunk {
+    walk_data: Storage<u64>,
+    bd_data: Storage<u64>,
+    se_data: Storage<u8>,
+}
+
+impl Chunk {
+    /// Reads the chunk's files into memory.
+    pub fn load(chunk_x: usize, chunk_y: usize, floor: usize, paths: &DataPaths) -> Result<Chunk> {
+        Ok(Chunk {
+            walk_data: Storage::read(&paths.walk(chunk_x, chunk_y, floor), 2)?,
+            bd_data: Storage::read(&paths.bd(chunk_x, chunk_y, floor), 7)?,
+            se_data: Storage::read(&paths.se(chunk_x, chunk_y, floor), 8)?,
+        })
+    }
+
+    /// Maps the chunk's files instead of reading them, so only the parts searches touch are ever paged
+    /// in. The files must not change while the chunk is alive.
+    pub fn map(chunk_x: usize, chunk_y: usize, floor: usize, paths: &DataPaths) -> Result<Chunk> {
+        Ok(Chunk {
+            walk_data: Storage::map(&paths.walk(chunk_x, chunk_y, floor), 2)?,
+            bd_data: Storage::map(&paths.bd(chunk_x, chunk_y, floor), 7)?,
+            se_data: Storage::map(&paths.se(chunk_x, chunk_y, floor), 8)?,
+        })
+    }
+
+    pub(crate) fn walk_data(&self) -> ArrayView3<'_, u64> {
+        self.walk_data.view()
+    }
+
+    pub(crate) fn bd_data(&self) -> ArrayView3<'_, u64> {
+        self.bd_data.view()
+    }
+
+    pub(crate) fn se_data(&self) -> ArrayView3<'_, u8> {
+        self.se_data.view()
+    }
+
+    /// Reads the chunk from its packed file, written by `chunk_format::convert`.
+    pub fn load_packed(chunk_x: usize, chunk_y: usize, floor: usize, paths: &DataPaths) -> Result<Chunk> {
+        let (walk_data, bd_data, se_data) = chunk_format::read_chunk(&paths.packed(chunk_x, chunk_y, floor))?;
+        Ok(Chunk {
+            walk_data: Storage::Owned(walk_data),
+            bd_data: Storage::Owned(bd_data),
+            se_data: Storage::Owned(se_data),
+        })
+    }
+
+    #[cfg(test)]
+    pub(crate) fn from_arrays(walk_data: Array3<u64>, bd_data: Array3<u64>, se_data: Array3<u8>) -> Chunk {
+        Chunk {
+            walk_data: Storage::Owned(walk_data),
+            bd_data: Storage::Owned(bd_data),
+            se_data: Storage::Owned(se_data),
+        }
+    }
+
+    /// Bytes held by the chunk's arrays, or mapped for them.
+    pub fn size(&self) -> usize {
+        self.walk_data.bytes() + self.bd_data.bytes() + self.se_data.bytes()
+    }
+}
+
+/// How a `ChunkCache` gets chunks off disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChunkFormat {
+    /// Read the `.npy` files with `Chunk::load`.
+    #[default]
+    Npy,
+    /// Map the `.npy` files with `Chunk::map`.
+    MappedNpy,
+    /// Read the compact files with `Chunk::load_packed`.
+    Packed,
+}
+
+/// Where map sections and world maps get their chunks from.
+pub trait ChunkSource {
+    fn chunk(&self, chunk_x: usize, chunk_y: usize, floor: usize) -> Result<Arc<Chunk>>;
+}
+
+/// Reads the chunk from disk every time.
+impl ChunkSource for DataPaths {
+    fn chunk(&self, chunk_x: usize, chunk_y: usize, floor: usize) -> Result<Arc<Chunk>> {
+        Chunk::load(chunk_x, chunk_y, floor, self).map(Arc::new)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Key {
+    Chunk(usize, usize, usize),
+    Page(usize, usize, usize),
+}
+
+#[derive(Debug, Clone)]
+enum Cached {
+    Chunk(Arc<Chunk>),
+    Page(Arc<FloorData>),
+}
+
+impl Cached {
+    fn size(&self) -> usize {
+        match self {
+            Cached::Chunk(chunk) => chunk.size(),
+            Cached::Page(page) => page.size(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    value: Cached,
+    last_used: u64,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    entries: HashMap<Key, Entry>,
+    bytes: usize,
+    clock: u64,
+}
+
+/// Reads chunks from the generated files in one format.
+struct ChunkFiles {
+    paths: DataPaths,
+    format: ChunkFormat,
+}
+
+impl ChunkSource for ChunkFiles {
+    fn chunk(&self, chunk_x: usize, chunk_y: usize, floor: usize) -> Result<Arc<Chunk>> {
+        let chunk = match self.format {
+            ChunkFormat::Npy => Chunk::load(chunk_x, chunk_y, floor, &self.paths)?,
+            ChunkFormat::MappedNpy => Chunk::map(chunk_x, chunk_y, floor, &self.paths)?,
+            ChunkFormat::Packed => Chunk::load_packed(chunk_x, chunk_y, floor, &self.paths)?,
+        };
+        Ok(Arc::new(chunk))
+    }
+}
+
+/// Chunks and the pages decoded from them, shared between threads. Both count towards one `budget` in
+/// bytes, and the least recently used are evicted beyond it. Anything bigger than `budget` on its own is
+/// handed out without being kept. Evicted chunks and pages stay alive for as long as a caller still holds
+/// them.
+pub struct ChunkCache {
+    source: Box<dyn ChunkSource + Send + Sync>,
+    budget: usize,
+    entries: Mutex<Entries>,
+}
+
+impl ChunkCache {
+    /// A cache reading the generated files under `paths` in `format`.
+    pub fn new(paths: &DataPaths, budget: usize, format: ChunkFormat) -> ChunkCache {
+        ChunkCache::with_source(ChunkFiles { paths: paths.clone(), format }, budget)
+    }
+
+    /// A cache in front of any other source of chunks.
+    pub fn with_source(source: impl ChunkSource + Send + Sync + 'static, budget: usize) -> ChunkCache {
+        ChunkCache {
+            source: Box::new(source),
+            budget,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    /// Bytes currently held by the cache.
+    pub fn bytes(&self) -> usize {
+        self.entries.lock().unwrap().bytes
+    }
+
+    /// The decoded page `(page_x, page_y)`, covering `PAGE_SIZE` tiles square from
+    /// `(page_x * PAGE_SIZE, page_y * PAGE_SIZE)`.
+    pub(crate) fn page(&self, page_x: usize, page_y: usize, floor: usize) -> Result<Arc<FloorData>> {
+        let page = self.get_or_load(Key::Page(page_x, page_y, floor), || {
+            let (x_start, y_start) = (page_x * PAGE_SIZE, page_y * PAGE_SIZE);
+            let chunk = self.chunk(x_start / CHUNK_SIZE, y_start / CHUNK_SIZE, floor)?;
+            let (u, v) = (x_start % CHUNK_SIZE, y_start % CHUNK_SIZE);
+            let window = s![u..u + PAGE_SIZE, v..v + PAGE_SIZE, ..];
+            let page = FloorData::new(x_start, x_start + PAGE_SIZE - 1, y_start, y_start + PAGE_SIZE - 1, chunk.walk_data().slice_move(window),
+                                      chunk.bd_data().slice_move(window), chunk.se_data().slice_move(window).to_owned());
+            Ok(Cached::Page(Arc::new(page)))
+        })?;
+        let Cached::Page(page) = page else { unreachable!("page keys only hold pages") };
+        Ok(page)
+    }
+
+    fn touch(entries: &mut Entries, key: Key) -> Option<Cached> {
+        entries.clock += 1;
+        let clock = entries.clock;
+        entries.entries.get_mut(&key).map(|entry| {
+            entry.last_used = clock;
+            entry.value.clone()
+        })
+    }
+
+    fn get_or_load(&self, key: Key, load: impl FnOnce() -> Result<Cached>) -> Result<Cached> {
+        if let Some(value) = ChunkCache::touch(&mut self.entries.lock().unwrap(), key) {
+            return Ok(value);
+        }
+        // Load without holding the lock so other threads are not held up by the disk. If another thread
+        // loads the same key meanwhile, its copy wins and this one is dropped.
+        let value = load()?;
+        // Caching something bigger than the whole budget would only push everything else out.
+        let size = value.size();
+        if size > self.budget {
+            return Ok(value);
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(value) = ChunkCache::touch(&mut entries, key) {
+            return Ok(value);
+        }
+        entries.bytes += size;
+        while entries.bytes > self.budget && !entries.entries.is_empty() {
+            let oldest = *entries.entries.iter().min_by_key(|(_, entry)| entry.last_used).unwrap().0;
+            let evicted = entries.entries.remove(&oldest).unwrap();
+            entries.bytes -= evicted.value.size();
+        }
+        let last_used = entries.clock;
+        entries.entries.insert(key, Entry { value: value.clone(), last_used });
+        Ok(value)
+    }
+}
+
+impl ChunkSource for ChunkCache {
+    fn chunk(&self, chunk_x: usize, chunk_y: usize, floor: usize) -> Result<Arc<Chunk>> {
+        let chunk = self.get_or_load(Key::Chunk(chunk_x, chunk_y, floor), || self.source.chunk(chunk_x, chunk_y, floor).map(Cached::Chunk))?;
+        let Cached::Chunk(chunk) = chunk else { unreachable!("chunk keys only hold chunks") };
+        Ok(chunk)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Hands out chunks of `side` by `side` tiles with no movement data, counting the loads of each.
+    pub(crate) struct FakeSource {
+        pub(crate) side: usize,
+        pub(crate) loads: Mutex<HashMap<(usize, usize, usize), usize>>,
+    }
+
+    impl FakeSource {
+        pub(crate) fn new(side: usize) -> FakeSource {
+            FakeSource { side, loads: Mutex::new(HashMap::new()) }
+        }
+
+        pub(crate) fn blank(&self) -> Chunk {
+            let side = self.side;
+            Chunk::from_arrays(Array3::from_elem([side, side, 2], u64::MAX), Array3::zeros([side, side, 7]), Array3::zeros([side, side, 8]))
+        }
+    }
+
+    impl ChunkSource for Arc<FakeSource> {
+        fn chunk(&self, chunk_x: usize, chunk_y: usize, floor: usize) -> Result<Arc<Chunk>> {
+            *self.loads.lock().unwrap().entry((chunk_x, chunk_y, floor)).or_default() += 1;
+            Ok(Arc::new(self.blank()))
+        }
+    }
+
+    #[test]
+    fn cache_evicts_the_least_recently_used_chunk() {
+        let source = Arc::new(FakeSource::new(4));
+        let size = source.blank().size();
+        let cache = ChunkCache::with_source(source.clone(), 2 * size);
+        let loads = |key| source.loads.lock().unwrap().get(&key).copied().unwrap_or(0);
+
+        cache.chunk(0, 0, 0).unwrap();
+        cache.chunk(1, 0, 0).unwrap();
+        // Using (0, 0) again makes (1, 0) the oldest, so (2, 0) replaces it.
+        cache.chunk(0, 0, 0).unwrap();
+        cache.chunk(2, 0, 0).unwrap();
+        assert_eq!(cache.bytes(), 2 * size);
+        cache.chunk(0, 0, 0).unwrap();
+        cache.chunk(2, 0, 0).unwrap();
+        assert_eq!((loads((0, 0, 0)), loads((1, 0, 0)), loads((2, 0, 0))), (1, 1, 1));
+        // (0, 0) was last used before (2, 0), so it makes room for (1, 0).
+        cache.chunk(1, 0, 0).unwrap();
+        cache.chunk(2, 0, 0).unwrap();
+        cache.chunk(0, 0, 0).unwrap();
+        assert_eq!((loads((0, 0, 0)), loads((1, 0, 0)), loads((2, 0, 0))), (2, 2, 1));
+        assert_eq!(cache.bytes(), 2 * size);
+    }
+
+    #[test]
+    fn chunks_bigger_than_the_budget_are_not_cached() {
+        let source = Arc::new(FakeSource::new(4));
+        let size = source.blank().size();
+        let cache = ChunkCache::with_source(source.clone(), size - 1);
+        cache.chunk(0, 0, 0).unwrap();
+        cache.chunk(0, 0, 0).unwrap();
+        assert_eq!(cache.bytes(), 0);
+        assert_eq!(source.loads.lock().unwrap()[&(0, 0, 0)], 2);
+    }
+}
@@ -0,0 +1,254 @@
+//! Compact container for a chunk's walk, Bladed Dive and Surge/Escape data.
+//!
+//! A file starts with a 24-byte header: the magic `RSMC`, then the format version, chunk size, block size,
+//! payload length and a CRC-32 of the payload, each a little-endian `u32`. The payload is one zlib stream
+//! holding the walk, BD and SE arrays in turn. Each array is cut into square blocks, written in x-major
+//! order, and a block whose every layer holds a single value (open ocean, mostly) is stored as just those
+//! values.
+
+use flate2::{write::ZlibEncoder, Compression};
+use ndarray::{s, Array3, ArrayView3};
+use std::{fs,
+          io::Write,
+          path::Path};
+use zune_inflate::DeflateDecoder;
+use crate::{chunk_cache::Chunk,
+            data_paths::DataPaths,
+            error::{Error, Result},
+            manifest::{Manifest, SourceHashes, Stage},
+            util::{CHUNK_SIZE, FLOORS, RS_HEIGHT, RS_LENGTH}};
+
+const MAGIC: &[u8; 4] = b"RSMC";
+pub(crate) const VERSION: u32 = 2;
+const HEADER_LEN: usize = 24;
+const BLOCK_SIZE: usize = 64;
+const UNIFORM: u8 = 0;
+const DENSE: u8 = 1;
+
+/// An element that can be written to and read from the payload as little-endian bytes.
+trait Element: Copy + PartialEq + Default {
+    const SIZE: usize;
+
+    fn write(self, out: &mut Vec<u8>);
+
+    fn read(bytes: &[u8]) -> Self;
+}
+
+impl Element for u64 {
+    const SIZE: usize = 8;
+
+    fn write(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn read(bytes: &[u8]) -> u64 {
+        u64::from_le_bytes(bytes.try_into().unwrap())
+    }
+}
+
+impl Element for u8 {
+    const SIZE: usize = 1;
+
+    fn write(self, out: &mut Vec<u8>) {
+        out.push(self);
+    }
+
+    fn read(bytes: &[u8]) -> u8 {
+        bytes[0]
+    }
+}
+
+/// Writes a chunk's arrays to `path` in the compact format.
+pub fn write_chunk(path: &Path, walk_data: ArrayView3<u64>, bd_data: ArrayView3<u64>, se_data: ArrayView3<u8>) -> Result<()> {
+    let mut payload = Vec::new();
+    write_blocks(&mut payload, walk_data);
+    write_blocks(&mut payload, bd_data);
+    write_blocks(&mut payload, se_data);
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&payload).map_err(|err| Error::io(path, err))?;
+    let compressed = encoder.finish().map_err(|err| Error::io(path, err))?;
+    let mut bytes = Vec::with_capacity(HEADER_LEN + compressed.len());
+    bytes.extend_from_slice(MAGIC);
+    for field in [VERSION, CHUNK_SIZE as u32, BLOCK_SIZE as u32, compressed.len() as u32, crc32fast::hash(&compressed)] {
+        bytes.extend_from_slice(&field.to_le_bytes());
+    }
+    bytes.extend_from_slice(&compressed);
+    fs::write(path, bytes).map_err(|err| Error::io(path, err))
+}
+
+/// Reads the walk, BD and SE arrays of a chunk written by `write_chunk`.
+pub fn read_chunk(path: &Path) -> Result<(Array3<u64>, Array3<u64>, Array3<u8>)> {
+    let corrupt = |message: &str| Error::Corrupt { path: path.to_path_buf(), message: message.to_string() };
+    let bytes = fs::read(path).map_err(|err| Error::io(path, err))?;
+    if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+        return Err(corrupt("not a packed chunk"));
+    }
+    let field = |i: usize| u32::from_le_bytes(bytes[4 * i..4 * i + 4].try_into().unwrap());
+    let (version, chunk_size, block_size, len, checksum) = (field(1), field(2), field(3), field(4) as usize, field(5));
+    if version != VERSION {
+        return Err(corrupt(&format!("format version {version}, expected {VERSION}")));
+    }
+    if chunk_size as usize != CHUNK_SIZE || block_size as usize != BLOCK_SIZE {
+        return Err(corrupt(&format!("chunk size {chunk_size} and block size {block_size} are not supported")));
+    }
+    if bytes.len() != HEADER_LEN + len {
+        return Err(corrupt("truncated payload"));
+    }
+    if crc32fast::hash(&bytes[HEADER_LEN..]) != checksum {
+        return Err(corrupt("checksum mismatch"));
+    }
+    let payload = DeflateDecoder::new(&bytes[HEADER_LEN..]).decode_zlib().map_err(|_| corrupt("invalid zlib stream"))?;
+    let mut rest = &payload[..];
+    let walk_data = read_blocks(&mut rest, 2).ok_or_else(|| corrupt("truncated walk data"))?;
+    let bd_data = read_blocks(&mut rest, 7).ok_or_else(|| corrupt("truncated BD data"))?;
+    let se_data = read_blocks(&mut rest, 8).ok_or_else(|| corrupt("truncated SE data"))?;
+    if !rest.is_empty() {
+        return Err(corrupt("trailing data"));
+    }
+    Ok((walk_data, bd_data, se_data))
+}
+
+fn write_blocks<A: Element>(out: &mut Vec<u8>, arr: ArrayView3<A>) {
+    let depth = arr.shape()[2];
+    for x in (0..CHUNK_SIZE).step_by(BLOCK_SIZE) {
+        for y in (0..CHUNK_SIZE).step_by(BLOCK_SIZE) {
+            let block = arr.slice(s![x..x + BLOCK_SIZE, y..y + BLOCK_SIZE, ..]);
+            let first: Vec<A> = (0..depth).map(|d| block[[0, 0, d]]).collect();
+            if block.indexed_iter().all(|((_, _, d), &value)| value == first[d]) {
+                out.push(UNIFORM);
+                first.into_iter().for_each(|value| value.write(out));
+            } else {
+                out.push(DENSE);
+                block.iter().for_each(|&value| value.write(out));
+            }
+        }
+    }
+}
+
+/// Takes one array of `depth` layers off the front of `rest`, or `None` if it runs out first.
+fn read_blocks<A: Element>(rest: &mut &[u8], depth: usize) -> Option<Array3<A>> {
+    let mut take = |len: usize| {
+        let (taken, remaining) = rest.split_at_checked(len)?;
+        *rest = remaining;
+        Some(taken)
+    };
+    let mut arr = Array3::default([CHUNK_SIZE, CHUNK_SIZE, depth]);
+    for x in (0..CHUNK_SIZE).step_by(BLOCK_SIZE) {
+        for y in (0..CHUNK_SIZE).step_by(BLOCK_SIZE) {
+            let mut block = arr.slice_mut(s![x..x + BLOCK_SIZE, y..y + BLOCK_SIZE, ..]);
+            match take(1)?[0] {
+                UNIFORM => {
+                    let values = take(depth * A::SIZE)?;
+                    for d in 0..depth {
+                        block.slice_mut(s![.., .., d]).fill(A::read(&values[d * A::SIZE..(d + 1) * A::SIZE]));
+                    }
+                }
+                DENSE => {
+                    let values = take(block.len() * A::SIZE)?;
+                    let mut values = values.chunks_exact(A::SIZE);
+                    for mut column in block.rows_mut() {
+                        column.iter_mut().zip(&mut values).for_each(|(value, bytes)| *value = A::read(bytes));
+                    }
+                }
+                _ => return None,
+            }
+        }
+    }
+    Some(arr)
+}
+
+/// Writes a packed copy of every generated `.npy` chunk whose copy is missing or, according to the
+/// manifest, was packed from different inputs or has changed since, or of every chunk if `reset`. Run it
+/// after `setup`, which brings the `.npy` chunks up to date first. Returns how many were written.
+pub fn convert(paths: &DataPaths, reset: bool) -> Result<usize> {
+    fs::create_dir_all(paths.map_dir.join("Packed")).map_err(|err| Error::io(&paths.map_dir, err))?;
+    let sources = SourceHashes::new(paths)?;
+    let mut manifest = Manifest::load(paths);
+    let mut written = 0;
+    for i in 0..RS_LENGTH / CHUNK_SIZE {
+        for j in 0..RS_HEIGHT / CHUNK_SIZE {
+            for k in 0..FLOORS {
+                let inputs = sources.inputs(Stage::Packed, i, j, k);
+                if !reset && !manifest.is_stale(Stage::Packed, i, j, k, inputs, paths) {
+                    continue;
+                }
+                let chunk = Chunk::load(i, j, k, paths)?;
+                write_chunk(&paths.packed(i, j, k), chunk.walk_data(), chunk.bd_data(), chunk.se_data())?;
+                manifest.record(Stage::Packed, i, j, k, inputs, paths)?;
+                written += 1;
+            }
+        }
+    }
+    manifest.save()?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_map::TempDir;
+
+    /// Arrays that are uniform apart from one dense block in each.
+    fn sample() -> (Array3<u64>, Array3<u64>, Array3<u8>) {
+        let mut walk_data = Array3::from_elem([CHUNK_SIZE, CHUNK_SIZE, 2], u64::MAX);
+        let mut bd_data = Array3::zeros([CHUNK_SIZE, CHUNK_SIZE, 7]);
+        let mut se_data = Array3::from_elem([CHUNK_SIZE, CHUNK_SIZE, 8], 0x11);
+        walk_data[[70, 3, 1]] = 0x1234;
+        bd_data[[CHUNK_SIZE - 1, CHUNK_SIZE - 1, 6]] = 1 << 40;
+        se_data[[600, 900, 5]] = 0x37;
+        (walk_data, bd_data, se_data)
+    }
+
+    fn write_sample(dir: &TempDir) -> std::path::PathBuf {
+        let path = dir.path().join("chunk.rsmc");
+        let (walk_data, bd_data, se_data) = sample();
+        write_chunk(&path, walk_data.view(), bd_data.view(), se_data.view()).unwrap();
+        path
+    }
+
+    fn corruption(path: &Path) -> String {
+        match read_chunk(path) {
+            Err(Error::Corrupt { message, .. }) => message,
+            other => panic!("expected a corrupt chunk, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn chunks_read_back_as_written() {
+        let dir = TempDir::new();
+        let path = write_sample(&dir);
+        assert_eq!(read_chunk(&path).unwrap(), sample());
+    }
+
+    #[test]
+    fn flipped_payload_bytes_fail_the_checksum() {
+        let dir = TempDir::new();
+        let path = write_sample(&dir);
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[HEADER_LEN + (last - HEADER_LEN) / 2] ^= 1;
+        fs::write(&path, bytes).unwrap();
+        assert_eq!(corruption(&path), "checksum mismatch");
+    }
+
+    #[test]
+    fn truncated_files_are_rejected() {
+        let dir = TempDir::new();
+        let path = write_sample(&dir);
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(corruption(&path), "truncated payload");
+        fs::write(&path, &bytes[..HEADER_LEN - 1]).unwrap();
+        assert_eq!(corruption(&path), "not a packed chunk");
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let dir = TempDir::new();
+        let path = write_sample(&dir);
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        fs::write(&path, bytes).unwrap();
+        assert_eq!(corruption(&path), format!("format version {}, expected {VERSION}", VERSION + 1));
+    }
+}
@@ -0,0 +1,69 @@
+use std::path::{Path, PathBuf};
+use crate::loadout::Loadout;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataPaths {
+    pub source_dir: PathBuf,
+    pub map_dir: PathBuf,
+    pub heuristic_dir: PathBuf,
+}
+
+impl Default for DataPaths {
+    fn default() -> Self {
+        DataPaths::new(".")
+    }
+}
+
+impl DataPaths {
+    /// Lays out `SourceData`, `MapData` and `HeuristicData` under `root`.
+    pub fn new(root: impl AsRef<Path>) -> DataPaths {
+        let root = root.as_ref();
+        DataPaths {
+            source_dir: root.join("SourceData"),
+            map_dir: root.join("MapData"),
+            heuristic_dir: root.join("HeuristicData"),
+        }
+    }
+
+    pub fn collision(&self, chunk_x: usize, chunk_y: usize, floor: usize) -> PathBuf {
+        self.source_dir.join(format!("collision-{chunk_x}-{chunk_y}-{floor}.bin"))
+    }
+
+    pub fn transports(&self) -> PathBuf {
+        self.source_dir.join("transports.csv")
+    }
+
+    pub fn teleports(&self) -> PathBuf {
+        self.source_dir.join("teleports.csv")
+    }
+
+    pub fn movement(&self, chunk_x: usize, chunk_y: usize, floor: usize) -> PathBuf {
+        self.map_dir.join("Move").join(format!("move-{chunk_x}-{chunk_y}-{floor}.npy"))
+    }
+
+    pub fn walk(&self, chunk_x: usize, chunk_y: usize, floor: usize) -> PathBuf {
+        self.map_dir.join("Walk").join(format!("walk-{chunk_x}-{chunk_y}-{floor}.npy"))
+    }
+
+    pub fn bd(&self, chunk_x: usize, chunk_y: usize, floor: usize) -> PathBuf {
+        self.map_dir.join("BD").join(format!("bd-{chunk_x}-{chunk_y}-{floor}.npy"))
+    }
+
+    pub fn se(&self, chunk_x: usize, chunk_y: usize, floor: usize) -> PathBuf {
+        self.map_dir.join("SE").join(format!("se-{chunk_x}-{chunk_y}-{floor}.npy"))
+    }
+
+    /// The chunk's walk, BD and SE data in the compact format of `chunk_format`.
+    pub fn packed(&self, chunk_x: usize, chunk_y: usize, floor: usize) -> PathBuf {
+        self.map_dir.join("Packed").join(format!("chunk-{chunk_x}-{chunk_y}-{floor}.rsmc"))
+    }
+
+    /// What each generated chunk file was built from, so `setup` can tell which are out of date.
+    pub fn manifest(&self) -> PathBuf {
+        self.map_dir.join("manifest.txt")
+    }
+
+    pub fn heuristic(&self, loadout: &Loadout) -> PathBuf {
+        self.heuristic_dir.join(format!("l_infinity_cds{}.npy", loadout.file_suffix()))
+    }
+}
@@ -0,0 +1,152 @@
+use ndarray::{Array, ArrayD, ArrayView, ArrayViewD, Dimension};
+use ndarray_npy::{read_npy, ReadNpyError, ReadableElement, ViewElement, ViewNpyError, ViewNpyExt};
+use std::{fmt, io, path::{Path, PathBuf}};
+
+#[derive(Debug)]
+pub enum Error {
+    MissingChunk { path: PathBuf },
+    WrongShape { path: PathBuf, expected: Vec<usize>, found: Vec<usize> },
+    WrongDtype { path: PathBuf },
+    Io { path: PathBuf, source: io::Error },
+    Npy { path: PathBuf, source: ReadNpyError },
+    NpyView { path: PathBuf, source: ViewNpyError },
+    Parse { path: PathBuf, line: usize, message: String },
+    Corrupt { path: PathBuf, message: String },
+    InvalidCooldowns { message: String },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub(crate) fn io(path: &Path, source: io::Error) -> Error {
+        if source.kind() == io::ErrorKind::NotFound {
+            Error::MissingChunk { path: path.to_path_buf() }
+        } else {
+            Error::Io { path: path.to_path_buf(), source }
+        }
+    }
+
+    fn npy(path: &Path, source: ReadNpyError) -> Error {
+        match source {
+            ReadNpyError::Io(source) => Error::io(path, source),
+            ReadNpyError::WrongDescriptor(_) => Error::WrongDtype { path: path.to_path_buf() },
+            source => Error::Npy { path: path.to_path_buf(), source },
+        }
+    }
+
+    fn npy_view(path: &Path, source: ViewNpyError) -> Error {
+        match source {
+            ViewNpyError::Io(source) => Error::io(path, source),
+            ViewNpyError::WrongDescriptor(_) => Error::WrongDtype { path: path.to_path_buf() },
+            source => Error::NpyView { path: path.to_path_buf(), source },
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::MissingChunk { path } => write!(f, "missing data file {}", path.display()),
+            Error::WrongShape { path, expected, found } => {
+                write!(f, "{} has shape {found:?}, expected {expected:?}", path.display())
+            }
+            Error::WrongDtype { path } => write!(f, "{} has the wrong element type", path.display()),
+            Error::Io { path, source } => write!(f, "I/O error on {}: {source}", path.display()),
+            Error::Npy { path, source } => write!(f, "failed to read {}: {source}", path.display()),
+            Error::NpyView { path, source } => write!(f, "failed to view {}: {source}", path.display()),
+            Error::Parse { path, line, message } => write!(f, "{}:{line}: {message}", path.display()),
+            Error::Corrupt { path, message } => write!(f, "{} is corrupt: {message}", path.display()),
+            Error::InvalidCooldowns { message } => write!(f, "invalid cooldown profile: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Npy { source, .. } => Some(source),
+            Error::NpyView { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Reads an `.npy` file and checks it against `expected`, where `None` accepts any length on that axis.
+pub(crate) fn read_array<A: ReadableElement, D: Dimension>(path: &Path, expected: &[Option<usize>]) -> Result<Array<A, D>> {
+    let arr: ArrayD<A> = read_npy(path).map_err(|err| Error::npy(path, err))?;
+    check_shape(path, arr.shape(), expected)?;
+    Ok(arr.into_dimensionality().expect("ndim checked above"))
+}
+
+/// Views the contents of an `.npy` file already in memory at `buf`, which was read from `path`, and checks
+/// it against `expected` like `read_array`.
+pub(crate) fn view_array<'a, A: ViewElement, D: Dimension>(path: &Path, buf: &'a [u8], expected: &[Option<usize>]) -> Result<ArrayView<'a, A, D>> {
+    let arr = ArrayViewD::<A>::view_npy(buf).map_err(|err| Error::npy_view(path, err))?;
+    check_shape(path, arr.shape(), expected)?;
+    Ok(arr.into_dimensionality().expect("ndim checked above"))
+}
+
+fn check_shape(path: &Path, shape: &[usize], expected: &[Option<usize>]) -> Result<()> {
+    let matches = shape.len() == expected.len()
+        && shape.iter().zip(expected).all(|(&found, expected)| expected.is_none_or(|e| e == found));
+    if !matches {
+        return Err(Error::WrongShape {
+            path: path.to_path_buf(),
+            expected: expected.iter().map(|e| e.unwrap_or(0)).collect(),
+            found: shape.to_vec(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::{Array3, Ix3};
+    use ndarray_npy::write_npy;
+    use std::fs;
+    use crate::{chunk_cache::Chunk, data_paths::DataPaths, test_map::TempDir};
+
+    #[test]
+    fn arrays_of_the_wrong_shape_are_rejected() {
+        let dir = TempDir::new();
+        let path = dir.path().join("walk.npy");
+        write_npy(&path, &Array3::<u64>::zeros([3, 2, 2])).unwrap();
+        let bytes = fs::read(&path).unwrap();
+
+        assert!(read_array::<u64, Ix3>(&path, &[Some(3), None, Some(2)]).is_ok());
+        assert!(view_array::<u64, Ix3>(&path, &bytes, &[None, Some(2), Some(2)]).is_ok());
+        for err in [read_array::<u64, Ix3>(&path, &[Some(3), Some(2), Some(7)]).unwrap_err(),
+                    view_array::<u64, Ix3>(&path, &bytes, &[Some(3), Some(2), Some(7)]).unwrap_err()] {
+            match err {
+                Error::WrongShape { path: p, expected, found } => {
+                    assert_eq!(p, path);
+                    assert_eq!(expected, [3, 2, 7]);
+                    assert_eq!(found, [3, 2, 2]);
+                }
+                err => panic!("expected WrongShape, got {err}"),
+            }
+        }
+        // A missing axis is a mismatch too, not a panic when converting the dimensions.
+        assert!(matches!(read_array::<u64, Ix3>(&path, &[Some(3), Some(2)]), Err(Error::WrongShape { .. })));
+        assert!(matches!(view_array::<u8, Ix3>(&path, &bytes, &[None, None, None]), Err(Error::WrongDtype { .. })));
+    }
+
+    #[test]
+    fn missing_chunk_files_are_reported_by_path() {
+        let dir = TempDir::new();
+        let paths = DataPaths::new(dir.path());
+        let missing = |result: Result<Chunk>| match result {
+            Err(Error::MissingChunk { path }) => path,
+            Err(err) => panic!("expected MissingChunk, got {err}"),
+            Ok(_) => panic!("expected MissingChunk, got a chunk"),
+        };
+
+        assert_eq!(missing(Chunk::load(1, 2, 3, &paths)), paths.walk(1, 2, 3));
+        assert_eq!(missing(Chunk::map(1, 2, 3, &paths)), paths.walk(1, 2, 3));
+        assert_eq!(missing(Chunk::load_packed(1, 2, 3, &paths)), paths.packed(1, 2, 3));
+        let err = read_array::<u64, Ix3>(&paths.bd(0, 0, 0), &[None, None, None]).unwrap_err();
+        assert_eq!(err.to_string(), format!("missing data file {}", paths.bd(0, 0, 0).display()));
+    }
+}
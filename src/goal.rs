@@ -0,0 +1,146 @@
+use std::{cmp::max, collections::HashSet};
+
+/// The set of tiles a search is allowed to finish on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Goal {
+    Tile { x: u16, y: u16 },
+    /// Every tile within Chebyshev distance `radius` of `(x, y)`.
+    Radius { x: u16, y: u16, radius: u16 },
+    Tiles(HashSet<(u16, u16)>),
+    /// Inclusive bounds. `Goal::rect` builds one from any two corners.
+    Rect { x_min: u16, x_max: u16, y_min: u16, y_max: u16 },
+    /// Restricts `goal` to one floor. The other variants match on any floor.
+    OnFloor { floor: u8, goal: Box<Goal> },
+    /// `goal` with an NPC standing on `(x, y)` that Barge can aim at, which need not be a goal tile.
+    BargeTarget { x: u16, y: u16, goal: Box<Goal> },
+}
+
+impl Goal {
+    /// Any tile next to or on `(x, y)`, which is what interacting with an NPC or object needs.
+    pub fn near(x: u16, y: u16) -> Goal {
+        Goal::Radius { x, y, radius: 1 }
+    }
+
+    /// The rectangle with opposite corners `(x1, y1)` and `(x2, y2)`, in either order.
+    pub fn rect(x1: u16, y1: u16, x2: u16, y2: u16) -> Goal {
+        Goal::Rect { x_min: x1.min(x2), x_max: x1.max(x2), y_min: y1.min(y2), y_max: y1.max(y2) }
+    }
+
+    pub fn on_floor(self, floor: u8) -> Goal {
+        Goal::OnFloor { floor, goal: Box::new(self) }
+    }
+
+    /// Lets Barge aim at an NPC on `(x, y)` while searching for this goal.
+    pub fn with_barge_target(self, x: u16, y: u16) -> Goal {
+        Goal::BargeTarget { x, y, goal: Box::new(self) }
+    }
+
+    pub fn floor(&self) -> Option<u8> {
+        match self {
+            Goal::OnFloor { floor, .. } => Some(*floor),
+            Goal::BargeTarget { goal, .. } => goal.floor(),
+            _ => None,
+        }
+    }
+
+    /// Whether `(x, y)` is a goal tile, ignoring floors.
+    pub fn contains(&self, x: u16, y: u16) -> bool {
+        self.distance(x, y) == 0
+    }
+
+    /// Chebyshev distance from `(x, y)` to the closest goal tile, ignoring floors.
+    pub fn distance(&self, x: u16, y: u16) -> usize {
+        match self {
+            Goal::Tile { x: u, y: v } => max(x.abs_diff(*u), y.abs_diff(*v)) as usize,
+            Goal::Radius { x: u, y: v, radius } => max(x.abs_diff(*u), y.abs_diff(*v)).saturating_sub(*radius) as usize,
+            Goal::Tiles(tiles) => tiles.iter()
+                .map(|&(u, v)| max(x.abs_diff(u), y.abs_diff(v)) as usize)
+                .min()
+                .unwrap_or(usize::MAX),
+            Goal::Rect { .. } => {
+                let (u, v) = self.closest_tile(x, y).unwrap();
+                max(x.abs_diff(u), y.abs_diff(v)) as usize
+            }
+            Goal::OnFloor { goal, .. } | Goal::BargeTarget { goal, .. } => goal.distance(x, y),
+        }
+    }
+
+    /// The goal tile closest to `(x, y)`, or `None` for an empty tile set.
+    pub fn closest_tile(&self, x: u16, y: u16) -> Option<(u16, u16)> {
+        match self {
+            Goal::Tile { x, y } => Some((*x, *y)),
+            Goal::Radius { x: u, y: v, radius } => {
+                Some((x.clamp(u.saturating_sub(*radius), u.saturating_add(*radius)), y.clamp(v.saturating_sub(*radius), v.saturating_add(*radius))))
+            }
+            Goal::Tiles(tiles) => tiles.iter().copied().min_by_key(|&(u, v)| max(x.abs_diff(u), y.abs_diff(v))),
+            // Built directly rather than through `Goal::rect`, the bounds may be the wrong way round.
+            Goal::Rect { x_min, x_max, y_min, y_max } => {
+                Some((x.clamp(*x_min.min(x_max), *x_min.max(x_max)), y.clamp(*y_min.min(y_max), *y_min.max(y_max))))
+            }
+            Goal::OnFloor { goal, .. } | Goal::BargeTarget { goal, .. } => goal.closest_tile(x, y),
+        }
+    }
+
+    /// Whether `f` holds for any goal tile, trying them in no particular order and ignoring floors.
+    pub fn any_tile(&self, f: &mut dyn FnMut(u16, u16) -> bool) -> bool {
+        match self {
+            Goal::Tile { x, y } => f(*x, *y),
+            Goal::Tiles(tiles) => tiles.iter().any(|&(x, y)| f(x, y)),
+            Goal::Radius { .. } | Goal::Rect { .. } => {
+                let (x_min, y_min) = self.closest_tile(0, 0).unwrap();
+                let (x_max, y_max) = self.closest_tile(u16::MAX, u16::MAX).unwrap();
+                (x_min..=x_max).any(|x| (y_min..=y_max).any(|y| f(x, y)))
+            }
+            Goal::OnFloor { goal, .. } | Goal::BargeTarget { goal, .. } => goal.any_tile(f),
+        }
+    }
+
+    /// Tile an NPC would be standing on, which is what Barge needs to aim at: the one given by
+    /// `with_barge_target`, or else the centre of a radius goal.
+    pub fn barge_target(&self) -> Option<(u16, u16)> {
+        match self {
+            Goal::BargeTarget { x, y, .. } => Some((*x, *y)),
+            Goal::Radius { x, y, radius } if *radius >= 1 => Some((*x, *y)),
+            Goal::OnFloor { goal, .. } => goal.barge_target(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rect_accepts_corners_in_any_order() {
+        let rect = Goal::rect(10, 2, 4, 8);
+        assert_eq!(rect, Goal::Rect { x_min: 4, x_max: 10, y_min: 2, y_max: 8 });
+        assert_eq!(rect.closest_tile(0, 20), Some((4, 8)));
+    }
+
+    #[test]
+    fn reversed_rect_bounds_do_not_panic() {
+        let rect = Goal::Rect { x_min: 10, x_max: 4, y_min: 8, y_max: 2 };
+        assert_eq!(rect.closest_tile(0, 20), Some((4, 8)));
+        assert_eq!(rect.distance(7, 5), 0);
+    }
+
+    #[test]
+    fn radius_near_the_top_of_the_range_does_not_overflow() {
+        let goal = Goal::Radius { x: u16::MAX - 1, y: 5, radius: 3 };
+        assert_eq!(goal.closest_tile(u16::MAX, 0), Some((u16::MAX, 2)));
+        assert!(goal.contains(u16::MAX, 8));
+    }
+
+    #[test]
+    fn any_tile_visits_the_whole_goal() {
+        let goal = Goal::near(5, 5).on_floor(1);
+        let mut seen = Vec::new();
+        assert!(!goal.any_tile(&mut |x, y| {
+            seen.push((x, y));
+            false
+        }));
+        assert_eq!(seen.len(), 9);
+        assert!(goal.any_tile(&mut |x, y| (x, y) == (6, 4)));
+    }
+}
@@ -1,5 +1,19 @@
+pub mod action;
+pub mod chunk_cache;
+pub mod chunk_format;
+pub mod data_paths;
+pub mod error;
+pub mod goal;
+pub mod loadout;
 pub mod state;
 pub mod map_section;
 pub mod util;
+pub mod world_map;
 pub mod preprocessing;
+pub mod route;
+mod manifest;
+mod search;
+#[cfg(test)]
+mod test_map;
+pub mod transport;
 pub mod pathfinding;
\ No newline at end of file
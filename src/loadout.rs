@@ -0,0 +1,225 @@
+use std::cmp::max;
+use crate::error::{Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AbilitySet {
+    pub surge: bool,
+    pub escape: bool,
+    pub bladed_dive: bool,
+    pub barge: bool,
+}
+
+/// Barge needs a target NPC, so it is left out of the default loadout.
+impl Default for AbilitySet {
+    fn default() -> Self {
+        AbilitySet { barge: false, ..AbilitySet::ALL }
+    }
+}
+
+impl AbilitySet {
+    pub const ALL: AbilitySet = AbilitySet { surge: true, escape: true, bladed_dive: true, barge: true };
+    pub const NONE: AbilitySet = AbilitySet { surge: false, escape: false, bladed_dive: false, barge: false };
+
+    /// True if every ability enabled in `other` is also enabled here. A heuristic built for a superset
+    /// loadout never overestimates for a subset, so it stays admissible.
+    pub fn contains(&self, other: &AbilitySet) -> bool {
+        (self.surge || !other.surge) && (self.escape || !other.escape) && (self.bladed_dive || !other.bladed_dive)
+            && (self.barge || !other.barge)
+    }
+
+    fn file_suffix(&self) -> String {
+        if *self == AbilitySet::default() {
+            return String::new();
+        }
+        let mut suffix = String::from("-");
+        if self.surge {
+            suffix.push('s');
+        }
+        if self.escape {
+            suffix.push('e');
+        }
+        if self.bladed_dive {
+            suffix.push('b');
+        }
+        if self.barge {
+            suffix.push('g');
+        }
+        suffix
+    }
+}
+
+/// Cooldowns in ticks. `lockout` is the short shared cooldown Surge and Escape put on each other.
+///
+/// With one charge each, Surge and Escape follow the legacy rules where `secd` is a cooldown shared by
+/// both abilities: using one starts `secd` at its cooldown, puts the other on its own cooldown and leaves
+/// the one just used behind only the lockout. With more charges, `scd` and `ecd` become recharge timers holding the ticks left until
+/// every charge is back, and `secd` only carries the lockout.
+/// `with_charges` checks that those timers fit in the `u8` cooldown fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CooldownProfile {
+    surge: u8,
+    escape: u8,
+    bladed_dive: u8,
+    barge: u8,
+    lockout: u8,
+    surge_charges: u8,
+    escape_charges: u8,
+}
+
+impl Default for CooldownProfile {
+    fn default() -> Self {
+        CooldownProfile {
+            surge: 17,
+            escape: 17,
+            bladed_dive: 17,
+            barge: 34,
+            lockout: 2,
+            surge_charges: 1,
+            escape_charges: 1,
+        }
+    }
+}
+
+impl CooldownProfile {
+    /// A profile with one charge of each ability.
+    pub fn new(surge: u8, escape: u8, bladed_dive: u8, barge: u8, lockout: u8) -> CooldownProfile {
+        CooldownProfile { surge, escape, bladed_dive, barge, lockout, surge_charges: 1, escape_charges: 1 }
+    }
+
+    /// The same profile with the given number of Surge and Escape charges. Fails if a full recharge timer,
+    /// the cooldown times the charges, does not fit in a `u8`.
+    pub fn with_charges(self, surge_charges: u8, escape_charges: u8) -> Result<CooldownProfile> {
+        let (surge_charges, escape_charges) = (surge_charges.max(1), escape_charges.max(1));
+        for (name, cooldown, charges) in [("Surge", self.surge, surge_charges), ("Escape", self.escape, escape_charges)] {
+            if cooldown.checked_mul(charges).is_none() {
+                return Err(Error::InvalidCooldowns {
+                    message: format!("{charges} charges of {name} with a {cooldown} tick cooldown need a recharge timer over {} ticks", u8::MAX),
+                });
+            }
+        }
+        Ok(CooldownProfile { surge_charges, escape_charges, ..self })
+    }
+
+    pub fn surge(&self) -> u8 {
+        self.surge
+    }
+
+    pub fn escape(&self) -> u8 {
+        self.escape
+    }
+
+    pub fn bladed_dive(&self) -> u8 {
+        self.bladed_dive
+    }
+
+    pub fn barge(&self) -> u8 {
+        self.barge
+    }
+
+    pub fn lockout(&self) -> u8 {
+        self.lockout
+    }
+
+    pub fn surge_charges(&self) -> u8 {
+        self.surge_charges
+    }
+
+    pub fn escape_charges(&self) -> u8 {
+        self.escape_charges
+    }
+
+    pub fn uses_charges(&self) -> bool {
+        self.surge_charges > 1 || self.escape_charges > 1
+    }
+
+    /// Highest recharge timer at which Surge still has a charge left.
+    pub fn surge_limit(&self) -> u8 {
+        self.surge * (self.surge_charges.max(1) - 1)
+    }
+
+    /// Highest recharge timer at which Escape still has a charge left.
+    pub fn escape_limit(&self) -> u8 {
+        self.escape * (self.escape_charges.max(1) - 1)
+    }
+
+    /// Largest values `secd`, `scd`, `ecd` and `bdcd` can take under this profile.
+    pub fn max_cooldowns(&self) -> [u8; 4] {
+        if self.uses_charges() {
+            [self.lockout, self.surge_limit() + self.surge, self.escape_limit() + self.escape, self.bladed_dive]
+        } else {
+            let se = max(max(self.surge, self.escape), self.lockout);
+            [se, se, se, self.bladed_dive]
+        }
+    }
+
+    fn file_suffix(&self) -> String {
+        // Barge only ever ends the route, so the heuristic does not depend on its cooldown.
+        let legacy = CooldownProfile { surge_charges: 1, escape_charges: 1, barge: CooldownProfile::default().barge, ..*self };
+        let mut suffix = String::new();
+        if legacy != CooldownProfile::default() {
+            suffix.push_str(&format!("-cd{}-{}-{}-{}", self.surge, self.escape, self.bladed_dive, self.lockout));
+        }
+        if self.uses_charges() {
+            suffix.push_str(&format!("-ch{}-{}", self.surge_charges, self.escape_charges));
+        }
+        suffix
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Loadout {
+    pub abilities: AbilitySet,
+    pub cooldowns: CooldownProfile,
+}
+
+impl Loadout {
+    pub fn new(abilities: AbilitySet, cooldowns: CooldownProfile) -> Loadout {
+        Loadout {
+            abilities,
+            cooldowns,
+        }
+    }
+
+    /// True if a heuristic generated for this loadout is admissible for a search using `other`.
+    pub fn admits(&self, other: &Loadout) -> bool {
+        self.abilities.contains(&other.abilities) && self.cooldowns == other.cooldowns
+    }
+
+    /// The same loadout with Barge left out, whose heuristic table covers the ticks Barge is on cooldown.
+    pub(crate) fn without_barge(&self) -> Loadout {
+        Loadout { abilities: AbilitySet { barge: false, ..self.abilities }, ..*self }
+    }
+
+    /// Suffix used to keep heuristic tables for different loadouts apart on disk. The default loadout
+    /// keeps the original unsuffixed file name.
+    pub(crate) fn file_suffix(&self) -> String {
+        format!("{}{}", self.abilities.file_suffix(), self.cooldowns.file_suffix())
+    }
+
+    /// Shape of the heuristic table for this loadout, with the distance axis left open.
+    pub(crate) fn heuristic_shape(&self) -> [Option<usize>; 5] {
+        let [secd, scd, ecd, bdcd] = self.cooldowns.max_cooldowns().map(|cd| Some(cd as usize + 1));
+        [None, secd, scd, ecd, bdcd]
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn charges_that_overflow_the_recharge_timer_are_rejected() {
+        let cooldowns = CooldownProfile::new(100, 17, 17, 34, 2);
+        assert!(matches!(cooldowns.with_charges(3, 1), Err(Error::InvalidCooldowns { .. })));
+        assert!(matches!(cooldowns.with_charges(1, 16), Err(Error::InvalidCooldowns { .. })));
+        assert!(cooldowns.with_charges(2, 15).is_ok());
+    }
+
+    #[test]
+    fn max_cooldowns_cover_every_charge() {
+        let cooldowns = CooldownProfile::new(85, 17, 17, 34, 2).with_charges(3, 2).unwrap();
+        assert_eq!((cooldowns.surge_limit(), cooldowns.escape_limit()), (170, 17));
+        assert_eq!(cooldowns.max_cooldowns(), [2, 255, 34, 17]);
+    }
+}
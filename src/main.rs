@@ -1,32 +1,54 @@
-use rs3_pathfinding::{map_section, pathfinding, state};
-use std::cmp::{max, min};
-use std::time::{Instant};
+use rs3_pathfinding::{action, chunk_cache::{ChunkCache, ChunkFormat}, data_paths::DataPaths, goal::Goal, loadout::Loadout, pathfinding, state,
+                      transport::{self, Player}, world_map::WorldMap};
+use std::{error::Error,
+          sync::Arc,
+          time::Instant};
 
+/// Bytes of chunks and decoded pages kept in memory between queries.
+const CACHE_BUDGET: usize = 2 << 30;
 
-
-fn main() {
-    rs3_pathfinding::preprocessing::setup(false);
+fn main() -> Result<(), Box<dyn Error>> {
+    let paths = DataPaths::default();
+    rs3_pathfinding::preprocessing::setup(false, &paths);
     let floor = 0;
     let start = state::State {
         pos_x: 2125,
         pos_y: 5146,
+        floor,
         direction: 4,
         secd: 0,
         scd: 0,
         ecd: 0,
         bdcd: 0,
+        bgcd: 0,
     };
     let end = (2134, 5162);
-    let radius = 120;
-    let section = map_section::MapSection::create_map_section(min(start.pos_x as usize, end.0 as usize) - radius,
-                                                              max(start.pos_x as usize, end.0 as usize) + radius,
-                                                              min(start.pos_y as usize, end.1 as usize) - radius,
-                                                              max(start.pos_y as usize, end.1 as usize) + radius, floor);
-    let heuristic = pathfinding::Heuristic::new();
+    // Every world map built on the cache shares the pages decoded into it, so later queries skip decoding.
+    let cache = Arc::new(ChunkCache::new(&paths, CACHE_BUDGET, ChunkFormat::MappedNpy));
+    let mut map = WorldMap::with_cache(cache);
+    if paths.transports().exists() {
+        map.add_transports(&transport::load_transports(&paths.transports())?);
+    }
+    if paths.teleports().exists() {
+        map.add_teleports(&transport::load_teleports(&paths.teleports())?);
+    }
+    let player = Player::default();
+    let loadout = Loadout::default();
+    let heuristic = pathfinding::Heuristic::new(&paths, &loadout)?;
     let now = Instant::now();
-    let (path, moves)  = pathfinding::a_star_end_buffer(start, end, &section, heuristic);
+    let result = pathfinding::a_star_end_buffer(start, &Goal::near(end.0, end.1), &map, &heuristic, &loadout, &player);
     let elapsed = now.elapsed();
-    println!("{:?}", moves);
-    println!("{:?}", path);
+    match result {
+        Ok(result) => {
+            println!("{:?}", result.ticks);
+            println!("{:?}", result.path);
+            println!("{:?}", action::action_plan(&result.path, &map, &loadout));
+        }
+        Err(err) => println!("{err}"),
+    }
+    for err in map.take_errors() {
+        println!("{err}");
+    }
     println!("Elapsed: {:.2?}", elapsed);
+    Ok(())
 }
@@ -0,0 +1,315 @@
+use ndarray::{Ix2, Ix3};
+use std::{collections::HashMap,
+          fmt::Write as _,
+          fs,
+          path::{Path, PathBuf}};
+use crate::{chunk_format,
+            data_paths::DataPaths,
+            error::{read_array, Error, Result},
+            util::{CHUNK_SIZE, FLOORS, RS_HEIGHT, RS_LENGTH}};
+
+/// A step that writes one file per chunk: one of the stages of `preprocessing::setup`, or the packed copy
+/// `chunk_format::convert` makes of their output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Stage {
+    Move,
+    Walk,
+    Bd,
+    Se,
+    Packed,
+}
+
+impl Stage {
+    /// The stages `setup` runs, in order.
+    pub(crate) const ALL: [Stage; 4] = [Stage::Move, Stage::Walk, Stage::Bd, Stage::Se];
+
+    fn name(self) -> &'static str {
+        match self {
+            Stage::Move => "move",
+            Stage::Walk => "walk",
+            Stage::Bd => "bd",
+            Stage::Se => "se",
+            Stage::Packed => "packed",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Stage> {
+        Stage::ALL.into_iter().chain([Stage::Packed]).find(|stage| stage.name() == name)
+    }
+
+    /// Bump when the stage's generator changes in a way that changes its output.
+    fn version(self) -> u32 {
+        match self {
+            Stage::Move => 1,
+            Stage::Walk => 1,
+            Stage::Bd => 1,
+            Stage::Se => 1,
+            Stage::Packed => chunk_format::VERSION,
+        }
+    }
+
+    /// The stages whose output this one reads, including itself. A change to any of their generators makes
+    /// this stage's output stale too.
+    fn lineage(self) -> &'static [Stage] {
+        match self {
+            Stage::Move => &[Stage::Move],
+            Stage::Walk => &[Stage::Move, Stage::Walk],
+            Stage::Bd => &[Stage::Move, Stage::Bd],
+            Stage::Se => &[Stage::Move, Stage::Bd, Stage::Se],
+            Stage::Packed => &[Stage::Move, Stage::Walk, Stage::Bd, Stage::Se, Stage::Packed],
+        }
+    }
+
+    pub(crate) fn output(self, paths: &DataPaths, chunk_x: usize, chunk_y: usize, floor: usize) -> PathBuf {
+        match self {
+            Stage::Move => paths.movement(chunk_x, chunk_y, floor),
+            Stage::Walk => paths.walk(chunk_x, chunk_y, floor),
+            Stage::Bd => paths.bd(chunk_x, chunk_y, floor),
+            Stage::Se => paths.se(chunk_x, chunk_y, floor),
+            Stage::Packed => paths.packed(chunk_x, chunk_y, floor),
+        }
+    }
+
+    /// Whether the file at `path` reads back as output of this stage: an array of the right element type
+    /// and shape, or a packed chunk whose checksum matches.
+    fn is_well_formed(self, path: &Path) -> bool {
+        let side = Some(CHUNK_SIZE);
+        match self {
+            Stage::Move => read_array::<u8, Ix2>(path, &[side, side]).is_ok(),
+            Stage::Walk => read_array::<u64, Ix3>(path, &[side, side, Some(2)]).is_ok(),
+            Stage::Bd => read_array::<u64, Ix3>(path, &[side, side, Some(7)]).is_ok(),
+            Stage::Se => read_array::<u8, Ix3>(path, &[side, side, Some(8)]).is_ok(),
+            Stage::Packed => chunk_format::read_chunk(path).is_ok(),
+        }
+    }
+}
+
+/// Hashes of the collision files, read once per `setup`.
+pub(crate) struct SourceHashes {
+    hashes: HashMap<(usize, usize, usize), u32>,
+}
+
+impl SourceHashes {
+    pub(crate) fn new(paths: &DataPaths) -> Result<SourceHashes> {
+        let mut hashes = HashMap::new();
+        for i in 0..RS_LENGTH / CHUNK_SIZE {
+            for j in 0..RS_HEIGHT / CHUNK_SIZE {
+                for k in 0..FLOORS {
+                    let path = paths.collision(i, j, k);
+                    let data = fs::read(&path).map_err(|err| Error::io(&path, err))?;
+                    hashes.insert((i, j, k), crc32fast::hash(&data));
+                }
+            }
+        }
+        Ok(SourceHashes { hashes })
+    }
+
+    /// Hash of everything `stage`'s output for the chunk depends on: the generator versions and the
+    /// collision data of the chunk and, past `Move`, its neighbours, since walk and Bladed Dive ranges
+    /// cross chunk borders.
+    pub(crate) fn inputs(&self, stage: Stage, chunk_x: usize, chunk_y: usize, floor: usize) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        for upstream in stage.lineage() {
+            hasher.update(&upstream.version().to_le_bytes());
+        }
+        let reach = if stage == Stage::Move { 0 } else { 1 };
+        for i in chunk_x.saturating_sub(reach)..=chunk_x + reach {
+            for j in chunk_y.saturating_sub(reach)..=chunk_y + reach {
+                if let Some(hash) = self.hashes.get(&(i, j, floor)) {
+                    hasher.update(&[i as u8, j as u8]);
+                    hasher.update(&hash.to_le_bytes());
+                }
+            }
+        }
+        hasher.finalize()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Entry {
+    inputs: u32,
+    size: u64,
+    /// CRC-32 of the file's contents.
+    content: u32,
+}
+
+impl Entry {
+    /// Reads the file at `path` to describe it as built from `inputs`, or `None` if it cannot be read.
+    fn of_file(path: &Path, inputs: u32) -> Option<Entry> {
+        let data = fs::read(path).ok()?;
+        Some(Entry { inputs, size: data.len() as u64, content: crc32fast::hash(&data) })
+    }
+}
+
+/// Record of what each generated chunk file was built from, kept at `DataPaths::manifest` as one
+/// `stage chunk_x chunk_y floor inputs size content` line per file. Lines that do not parse are ignored,
+/// which leaves their chunks unrecorded.
+pub(crate) struct Manifest {
+    path: PathBuf,
+    entries: HashMap<(Stage, usize, usize, usize), Entry>,
+}
+
+impl Manifest {
+    pub(crate) fn load(paths: &DataPaths) -> Manifest {
+        let path = paths.manifest();
+        let text = fs::read_to_string(&path).unwrap_or_default();
+        let entries = text.lines()
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                let [stage, chunk_x, chunk_y, floor, inputs, size, content] = fields[..] else {
+                    return None;
+                };
+                let key = (Stage::from_name(stage)?, chunk_x.parse().ok()?, chunk_y.parse().ok()?, floor.parse().ok()?);
+                let entry = Entry {
+                    inputs: u32::from_str_radix(inputs, 16).ok()?,
+                    size: size.parse().ok()?,
+                    content: u32::from_str_radix(content, 16).ok()?,
+                };
+                Some((key, entry))
+            })
+            .collect();
+        Manifest { path, entries }
+    }
+
+    /// Whether the chunk's file is missing, was built from different inputs, or no longer has the size and
+    /// contents it was written with.
+    ///
+    /// A file with no entry, as in a `MapData` directory generated before there was a manifest, is adopted
+    /// rather than regenerated if it reads back as output of `stage`: it is recorded as built from `inputs`,
+    /// and kept by the next `save`.
+    pub(crate) fn is_stale(&mut self, stage: Stage, chunk_x: usize, chunk_y: usize, floor: usize, inputs: u32, paths: &DataPaths) -> bool {
+        let output = stage.output(paths, chunk_x, chunk_y, floor);
+        let key = (stage, chunk_x, chunk_y, floor);
+        match self.entries.get(&key) {
+            Some(entry) => {
+                // Only read the file when the cheaper checks pass.
+                let unchanged = entry.inputs == inputs
+                    && fs::metadata(&output).is_ok_and(|metadata| metadata.len() == entry.size)
+                    && Entry::of_file(&output, inputs).as_ref() == Some(entry);
+                !unchanged
+            }
+            None => match Entry::of_file(&output, inputs).filter(|_| stage.is_well_formed(&output)) {
+                Some(entry) => {
+                    self.entries.insert(key, entry);
+                    false
+                }
+                None => true,
+            },
+        }
+    }
+
+    /// Notes that the chunk's file was just written from `inputs`, and saves the manifest.
+    pub(crate) fn record(&mut self, stage: Stage, chunk_x: usize, chunk_y: usize, floor: usize, inputs: u32, paths: &DataPaths) -> Result<()> {
+        let output = stage.output(paths, chunk_x, chunk_y, floor);
+        let data = fs::read(&output).map_err(|err| Error::io(&output, err))?;
+        let entry = Entry { inputs, size: data.len() as u64, content: crc32fast::hash(&data) };
+        self.entries.insert((stage, chunk_x, chunk_y, floor), entry);
+        self.save()
+    }
+
+    pub(crate) fn save(&self) -> Result<()> {
+        let mut keys: Vec<_> = self.entries.keys().collect();
+        keys.sort_by_key(|(stage, chunk_x, chunk_y, floor)| (stage.name(), *chunk_x, *chunk_y, *floor));
+        let mut text = String::new();
+        for key @ (stage, chunk_x, chunk_y, floor) in keys {
+            let entry = self.entries[key];
+            writeln!(text, "{} {chunk_x} {chunk_y} {floor} {:08x} {} {:08x}", stage.name(), entry.inputs, entry.size, entry.content).unwrap();
+        }
+        // Write then rename, so an interrupted save never leaves a half-written manifest behind.
+        let temp = self.path.with_extension("txt.tmp");
+        fs::write(&temp, text).map_err(|err| Error::io(&temp, err))?;
+        fs::rename(&temp, &self.path).map_err(|err| Error::io(&self.path, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::{Array2, Array3};
+    use ndarray_npy::write_npy;
+    use crate::test_map::{write_collision_data, TempDir};
+
+    #[test]
+    fn changed_collision_data_only_makes_its_chunk_and_neighbours_stale() {
+        let dir = TempDir::new();
+        let paths = DataPaths::new(dir.path());
+        write_collision_data(&paths, &[]);
+        let before = SourceHashes::new(&paths).unwrap();
+        let stages: Vec<_> = Stage::ALL.into_iter().chain([Stage::Packed]).collect();
+        let chunks: Vec<_> = (0..RS_LENGTH / CHUNK_SIZE)
+            .flat_map(|i| (0..RS_HEIGHT / CHUNK_SIZE).flat_map(move |j| (0..FLOORS).map(move |k| (i, j, k))))
+            .collect();
+        let mut manifest = Manifest::load(&paths);
+        for &stage in &stages {
+            for &(i, j, k) in &chunks {
+                let output = stage.output(&paths, i, j, k);
+                fs::create_dir_all(output.parent().unwrap()).unwrap();
+                fs::write(&output, stage.name()).unwrap();
+                manifest.record(stage, i, j, k, before.inputs(stage, i, j, k), &paths).unwrap();
+            }
+        }
+
+        write_collision_data(&paths, &[(2 * CHUNK_SIZE + 5, 3 * CHUNK_SIZE + 5, 1, 255)]);
+        let after = SourceHashes::new(&paths).unwrap();
+        let mut manifest = Manifest::load(&paths);
+        for &stage in &stages {
+            let reach = if stage == Stage::Move { 0 } else { 1 };
+            for &(i, j, k) in &chunks {
+                let affected = k == 1 && i.abs_diff(2) <= reach && j.abs_diff(3) <= reach;
+                let (old, new) = (before.inputs(stage, i, j, k), after.inputs(stage, i, j, k));
+                assert_eq!(old != new, affected, "{stage:?} ({i}, {j}, {k})");
+                assert!(!manifest.is_stale(stage, i, j, k, old, &paths), "{stage:?} ({i}, {j}, {k})");
+                assert_eq!(manifest.is_stale(stage, i, j, k, new, &paths), affected, "{stage:?} ({i}, {j}, {k})");
+            }
+        }
+    }
+
+    #[test]
+    fn missing_resized_or_changed_outputs_are_stale() {
+        let dir = TempDir::new();
+        let paths = DataPaths::new(dir.path());
+        let output = Stage::Walk.output(&paths, 1, 2, 0);
+        let mut manifest = Manifest::load(&paths);
+        assert!(manifest.is_stale(Stage::Walk, 1, 2, 0, 7, &paths));
+        fs::create_dir_all(output.parent().unwrap()).unwrap();
+        fs::write(&output, "walk").unwrap();
+        manifest.record(Stage::Walk, 1, 2, 0, 7, &paths).unwrap();
+        assert!(!manifest.is_stale(Stage::Walk, 1, 2, 0, 7, &paths));
+        assert!(manifest.is_stale(Stage::Bd, 1, 2, 0, 7, &paths));
+        fs::write(&output, "walk data").unwrap();
+        assert!(manifest.is_stale(Stage::Walk, 1, 2, 0, 7, &paths));
+        // Same size, different contents.
+        manifest.record(Stage::Walk, 1, 2, 0, 7, &paths).unwrap();
+        fs::write(&output, "walk dat4").unwrap();
+        assert!(manifest.is_stale(Stage::Walk, 1, 2, 0, 7, &paths));
+        assert!(Manifest::load(&paths).is_stale(Stage::Walk, 1, 2, 0, 7, &paths));
+    }
+
+    #[test]
+    fn unrecorded_outputs_are_adopted_if_they_read_back() {
+        let dir = TempDir::new();
+        let paths = DataPaths::new(dir.path());
+        let output = |stage: Stage| {
+            let output = stage.output(&paths, 1, 2, 0);
+            fs::create_dir_all(output.parent().unwrap()).unwrap();
+            output
+        };
+        write_npy(output(Stage::Move), &Array2::<u8>::zeros([CHUNK_SIZE, CHUNK_SIZE])).unwrap();
+        // Cut short, the wrong shape and the wrong element type.
+        fs::write(output(Stage::Walk), &fs::read(output(Stage::Move)).unwrap()[..1000]).unwrap();
+        write_npy(output(Stage::Bd), &Array3::<u64>::zeros([CHUNK_SIZE, CHUNK_SIZE, 2])).unwrap();
+        write_npy(output(Stage::Se), &Array3::<u64>::zeros([CHUNK_SIZE, CHUNK_SIZE, 8])).unwrap();
+
+        let mut manifest = Manifest::load(&paths);
+        assert!(!manifest.is_stale(Stage::Move, 1, 2, 0, 7, &paths));
+        for stage in [Stage::Walk, Stage::Bd, Stage::Se, Stage::Packed] {
+            assert!(manifest.is_stale(stage, 1, 2, 0, 7, &paths), "{stage:?}");
+        }
+        manifest.save().unwrap();
+
+        // Adopted as built from the inputs it was checked against, so other inputs still make it stale.
+        let mut manifest = Manifest::load(&paths);
+        assert!(manifest.is_stale(Stage::Move, 1, 2, 0, 8, &paths));
+        assert!(!manifest.is_stale(Stage::Move, 1, 2, 0, 7, &paths));
+    }
+}
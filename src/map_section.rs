@@ -1,28 +1,116 @@
-use ndarray::{Array3, Axis, concatenate};
-use ndarray_npy::read_npy;
+use ndarray::{Array3, ArrayView3};
 use std::{collections::{HashMap},
-          cmp};
+          sync::Arc};
+use crate::{chunk_cache::{Chunk, ChunkSource},
+            error::Result,
+            transport::{Teleport, Transport},
+            util::{CHUNK_SIZE, RS_HEIGHT, RS_LENGTH}};
 
+/// Reachable tiles for every tile of a rectangle, stored flat: the tiles for the `i`th tile in x-major
+/// order are `tiles[offsets[i]..offsets[i + 1]]`.
 #[derive(Debug)]
-pub struct MapSection {
-    #[allow(dead_code)]
-    floor: usize,
+struct TileMap {
+    offsets: Vec<u32>,
+    tiles: Vec<(u16, u16, u8)>,
+}
+
+impl TileMap {
+    fn with_capacity(tile_count: usize, tiles_per_tile: usize) -> TileMap {
+        let mut offsets = Vec::with_capacity(tile_count + 1);
+        offsets.push(0);
+        TileMap {
+            offsets,
+            tiles: Vec::with_capacity(tile_count * tiles_per_tile),
+        }
+    }
+
+    /// Ends the current tile; everything pushed since the previous call belongs to it.
+    fn finish_tile(&mut self) {
+        self.offsets.push(self.tiles.len() as u32);
+    }
+
+    fn size(&self) -> usize {
+        self.offsets.capacity() * size_of::<u32>() + self.tiles.capacity() * size_of::<(u16, u16, u8)>()
+    }
+
+    fn get(&self, index: usize) -> &[(u16, u16, u8)] {
+        &self.tiles[self.offsets[index] as usize..self.offsets[index + 1] as usize]
+    }
+}
+
+/// Movement data a search needs. Implemented by `MapSection` for fixed rectangles and by
+/// `WorldMap` for the whole world paged in on demand.
+pub trait MapView {
+    /// Whether `(x, y)` on `floor` has movement data available.
+    fn is_loaded(&self, x: u16, y: u16, floor: u8) -> bool;
+
+    fn walk_range(&self, x: u16, y: u16, floor: u8) -> &[(u16, u16, u8)];
+
+    fn bd_range(&self, x: u16, y: u16, floor: u8) -> &[(u16, u16, u8)];
+
+    fn surge_range(&self, x: u16, y: u16, floor: u8, direction: u8) -> (usize, usize);
+
+    fn escape_range(&self, x: u16, y: u16, floor: u8, direction: u8) -> (usize, usize);
+
+    fn transports(&self, x: u16, y: u16, floor: u8) -> &[Transport];
+
+    fn all_transports(&self) -> impl Iterator<Item = &Transport>;
+
+    fn teleports(&self) -> &[Teleport];
+}
+
+/// Decoded movement data for one floor of a rectangle starting at `(x_start, y_start)`.
+#[derive(Debug)]
+pub(crate) struct FloorData {
     x_start: usize,
-    #[allow(dead_code)]
-    x_end: usize,
     y_start: usize,
-    #[allow(dead_code)]
-    y_end: usize,
+    y_len: usize,
     se_data: Array3<u8>,
-    walk_hashmap: HashMap<(u16, u16), Vec<(u16, u16, u8)>>,
-    bd_hashmap: HashMap<(u16, u16), Vec<(u16, u16, u8)>>,
+    walk_map: TileMap,
+    bd_map: TileMap,
 }
 
-impl MapSection {
+impl FloorData {
+    /// Decodes the rectangle from arrays starting at `(x_start, y_start)`.
+    pub(crate) fn new(x_start: usize, x_end: usize, y_start: usize, y_end: usize, walk_data: ArrayView3<u64>, bd_data: ArrayView3<u64>, se_data: Array3<u8>) -> FloorData {
+        FloorData {
+            x_start,
+            y_start,
+            y_len: y_end - y_start + 1,
+            se_data,
+            walk_map: build_walk_map(x_start, x_end, y_start, y_end, |x, y, i| walk_data[[x - x_start, y - y_start, i]]),
+            bd_map: build_bd_map(x_start, x_end, y_start, y_end, |x, y, i| bd_data[[x - x_start, y - y_start, i]]),
+        }
+    }
+
+    /// Decodes the rectangle straight from the chunks it overlaps. Only its Surge/Escape bytes are copied
+    /// out of them; walk and Bladed Dive data are read in place.
+    fn load(x_start: usize, x_end: usize, y_start: usize, y_end: usize, floor: usize, source: &impl ChunkSource) -> Result<FloorData> {
+        let chunks = (y_start / CHUNK_SIZE..=y_end / CHUNK_SIZE)
+            .map(|j| (x_start / CHUNK_SIZE..=x_end / CHUNK_SIZE).map(|i| source.chunk(i, j, floor)).collect())
+            .collect::<Result<Vec<Vec<_>>>>()?;
+        let walk_data = ChunkViews::new(x_start, y_start, &chunks, Chunk::walk_data);
+        let bd_data = ChunkViews::new(x_start, y_start, &chunks, Chunk::bd_data);
+        let se_data = ChunkViews::new(x_start, y_start, &chunks, Chunk::se_data);
+        Ok(FloorData {
+            x_start,
+            y_start,
+            y_len: y_end - y_start + 1,
+            se_data: Array3::from_shape_fn([x_end - x_start + 1, y_end - y_start + 1, 8],
+                                           |(u, v, i)| se_data.get(x_start + u, y_start + v, i)),
+            walk_map: build_walk_map(x_start, x_end, y_start, y_end, |x, y, i| walk_data.get(x, y, i)),
+            bd_map: build_bd_map(x_start, x_end, y_start, y_end, |x, y, i| bd_data.get(x, y, i)),
+        })
+    }
+
+    /// Bytes held by the decoded data.
+    pub(crate) fn size(&self) -> usize {
+        self.se_data.len() + self.walk_map.size() + self.bd_map.size()
+    }
 
-    pub fn surge_range(&self, x: u16, y: u16, direction: u8) -> (usize, usize) {
+    pub(crate) fn surge_range(&self, x: u16, y: u16, direction: u8) -> (usize, usize) {
         let (x, y, direction) = (x as usize, y as usize, direction as usize);
-        let offset = (self.se_data[[x-self.x_start, y - self.y_start, direction]] & 15) as usize;
+        let offset = (self.se_data[[x - self.x_start, y - self.y_start, direction]] & 15) as usize;
         match direction {
             0 => (x, y + offset),
             1 => (x + offset, y + offset),
@@ -36,9 +124,9 @@ impl MapSection {
         }
     }
 
-    pub fn escape_range(&self, x: u16, y: u16, direction: u8) -> (usize, usize) {
+    pub(crate) fn escape_range(&self, x: u16, y: u16, direction: u8) -> (usize, usize) {
         let (x, y, direction) = (x as usize, y as usize, direction as usize);
-        let offset = (self.se_data[[x-self.x_start, y - self.y_start, direction]] >> 4) as usize;
+        let offset = (self.se_data[[x - self.x_start, y - self.y_start, direction]] >> 4) as usize;
         match direction {
             0 => (x, y - offset),
             1 => (x - offset, y - offset),
@@ -52,44 +140,193 @@ impl MapSection {
         }
     }
 
-    pub fn create_map_section(x_start: usize, x_end: usize, y_start: usize, y_end: usize, floor: usize) -> MapSection {
-        let bd_data = build_bd_array(x_start, x_end, y_start, y_end, floor);
-        let se_data = build_se_array(x_start, x_end, y_start, y_end, floor);
-        let walk_data = build_walk_array(x_start, x_end, y_start, y_end, floor);
-        let walk_hashmap = build_walk_hashmap(x_start, x_end, y_start, y_end, &walk_data);
-        let bd_hashmap = build_bd_hashmap(x_start, x_end, y_start, y_end, &bd_data);
-        MapSection {
-            floor,
-            x_start,
+    pub(crate) fn walk_range(&self, x: u16, y: u16) -> &[(u16, u16, u8)] {
+        self.walk_map.get(self.index(x, y))
+    }
+
+    pub(crate) fn bd_range(&self, x: u16, y: u16) -> &[(u16, u16, u8)] {
+        self.bd_map.get(self.index(x, y))
+    }
+
+    fn index(&self, x: u16, y: u16) -> usize {
+        (x as usize - self.x_start) * self.y_len + (y as usize - self.y_start)
+    }
+}
+
+/// A rectangle of the map, loaded for one or more floors.
+#[derive(Debug)]
+struct Region {
+    x_start: usize,
+    x_end: usize,
+    y_start: usize,
+    y_end: usize,
+    floors: HashMap<usize, FloorData>,
+}
+
+impl Region {
+    /// A region covering `x_start..=x_end` by `y_start..=y_end`, clamped to the world.
+    fn new(x_start: usize, x_end: usize, y_start: usize, y_end: usize) -> Region {
+        let (x_end, y_end) = (x_end.min(RS_LENGTH - 1), y_end.min(RS_HEIGHT - 1));
+        Region {
+            x_start: x_start.min(x_end),
             x_end,
-            y_start,
+            y_start: y_start.min(y_end),
             y_end,
-            se_data,
-            walk_hashmap,
-            bd_hashmap,
+            floors: HashMap::new(),
         }
     }
 
-    pub fn walk_range(&self, x: u16, y: u16) -> &Vec<(u16, u16, u8)> {
-        self.walk_hashmap.get(&(x, y)).unwrap()
+    fn contains(&self, x: u16, y: u16) -> bool {
+        let (x, y) = (x as usize, y as usize);
+        self.x_start <= x && x <= self.x_end && self.y_start <= y && y <= self.y_end
     }
 
-    pub fn bd_range(&self, x: u16, y: u16) -> &Vec<(u16, u16, u8)> {
-        self.bd_hashmap.get(&(x, y)).unwrap()
+    fn load_floor(&mut self, floor: usize, source: &impl ChunkSource) -> Result<()> {
+        if !self.floors.contains_key(&floor) {
+            let data = FloorData::load(self.x_start, self.x_end, self.y_start, self.y_end, floor, source)?;
+            self.floors.insert(floor, data);
+        }
+        Ok(())
     }
 }
 
-fn build_bd_hashmap(x_start: usize, x_end: usize, y_start: usize, y_end: usize, arr: &Array3<u64>) -> HashMap<(u16, u16), Vec<(u16, u16, u8)>> {
-    let mut bd_hashmap = HashMap::new();
+/// One or more rectangles of the map, each loaded for one or more floors, along with the transports that
+/// start in them and the teleports usable from anywhere. Tiles outside every loaded rectangle are blocked.
+#[derive(Debug)]
+pub struct MapSection {
+    regions: Vec<Region>,
+    transports: HashMap<(u16, u16, u8), Vec<Transport>>,
+    teleports: Vec<Teleport>,
+}
+
+impl MapSection {
+    /// Loads `floor` of the rectangle `x_start..=x_end` by `y_start..=y_end`. Bounds past the edge of the
+    /// world are clamped to it.
+    pub fn create_map_section(x_start: usize, x_end: usize, y_start: usize, y_end: usize, floor: usize, source: &impl ChunkSource) -> Result<MapSection> {
+        let mut section = MapSection {
+            regions: Vec::new(),
+            transports: HashMap::new(),
+            teleports: Vec::new(),
+        };
+        section.add_region(x_start, x_end, y_start, y_end, floor, source)?;
+        Ok(section)
+    }
+
+    /// Loads `floor` of the tiles within `radius` of any of `points`, clamped to the world.
+    pub fn around(points: &[(u16, u16)], radius: usize, floor: usize, source: &impl ChunkSource) -> Result<MapSection> {
+        let xs = points.iter().map(|point| point.0 as usize);
+        let ys = points.iter().map(|point| point.1 as usize);
+        let (x_min, x_max) = (xs.clone().min().unwrap_or(0), xs.max().unwrap_or(0));
+        let (y_min, y_max) = (ys.clone().min().unwrap_or(0), ys.max().unwrap_or(0));
+        MapSection::create_map_section(x_min.saturating_sub(radius), x_max + radius, y_min.saturating_sub(radius), y_max + radius, floor, source)
+    }
+
+    /// Loads another rectangle, clamped to the world. Where rectangles overlap, the one added first is used.
+    pub fn add_region(&mut self, x_start: usize, x_end: usize, y_start: usize, y_end: usize, floor: usize, source: &impl ChunkSource) -> Result<()> {
+        let mut region = Region::new(x_start, x_end, y_start, y_end);
+        region.load_floor(floor, source)?;
+        self.regions.push(region);
+        Ok(())
+    }
+
+    /// Loads `floor` in every region.
+    pub fn load_floor(&mut self, floor: usize, source: &impl ChunkSource) -> Result<()> {
+        for region in &mut self.regions {
+            region.load_floor(floor, source)?;
+        }
+        Ok(())
+    }
+
+    /// Adds the transports with both ends inside the section, loading every floor they lead to.
+    pub fn add_transports<'a>(&mut self, transports: impl IntoIterator<Item = &'a Transport>, source: &impl ChunkSource) -> Result<()> {
+        for transport in transports {
+            if !self.contains(transport.from_x, transport.from_y) || !self.contains(transport.to_x, transport.to_y) {
+                continue;
+            }
+            for region in &mut self.regions {
+                if region.contains(transport.from_x, transport.from_y) {
+                    region.load_floor(transport.from_floor as usize, source)?;
+                }
+                if region.contains(transport.to_x, transport.to_y) {
+                    region.load_floor(transport.to_floor as usize, source)?;
+                }
+            }
+            self.transports.entry((transport.from_x, transport.from_y, transport.from_floor))
+                .or_default()
+                .push(transport.clone());
+        }
+        Ok(())
+    }
+
+    /// Adds teleports, loading a region of `radius` tiles around each arrival point that is not loaded yet.
+    pub fn add_teleports<'a>(&mut self, teleports: impl IntoIterator<Item = &'a Teleport>, radius: usize, source: &impl ChunkSource) -> Result<()> {
+        for teleport in teleports {
+            if !self.is_loaded(teleport.to_x, teleport.to_y, teleport.to_floor) {
+                let (x, y) = (teleport.to_x as usize, teleport.to_y as usize);
+                self.add_region(x.saturating_sub(radius), x + radius, y.saturating_sub(radius), y + radius, teleport.to_floor as usize, source)?;
+            }
+            self.teleports.push(teleport.clone());
+        }
+        Ok(())
+    }
+
+    fn floor(&self, x: u16, y: u16, floor: u8) -> Option<&FloorData> {
+        self.regions.iter()
+            .filter(|region| region.contains(x, y))
+            .find_map(|region| region.floors.get(&(floor as usize)))
+    }
+
+    /// Whether `(x, y)` lies in any region of the section, on whichever floors it has loaded.
+    pub fn contains(&self, x: u16, y: u16) -> bool {
+        self.regions.iter().any(|region| region.contains(x, y))
+    }
+}
+
+impl MapView for MapSection {
+    fn is_loaded(&self, x: u16, y: u16, floor: u8) -> bool {
+        self.floor(x, y, floor).is_some()
+    }
+
+    fn walk_range(&self, x: u16, y: u16, floor: u8) -> &[(u16, u16, u8)] {
+        self.floor(x, y, floor).map_or(&[], |data| data.walk_range(x, y))
+    }
+
+    fn bd_range(&self, x: u16, y: u16, floor: u8) -> &[(u16, u16, u8)] {
+        self.floor(x, y, floor).map_or(&[], |data| data.bd_range(x, y))
+    }
+
+    fn surge_range(&self, x: u16, y: u16, floor: u8, direction: u8) -> (usize, usize) {
+        self.floor(x, y, floor).map_or((x as usize, y as usize), |data| data.surge_range(x, y, direction))
+    }
+
+    fn escape_range(&self, x: u16, y: u16, floor: u8, direction: u8) -> (usize, usize) {
+        self.floor(x, y, floor).map_or((x as usize, y as usize), |data| data.escape_range(x, y, direction))
+    }
+
+    fn transports(&self, x: u16, y: u16, floor: u8) -> &[Transport] {
+        self.transports.get(&(x, y, floor)).map_or(&[], Vec::as_slice)
+    }
+
+    fn all_transports(&self) -> impl Iterator<Item = &Transport> {
+        self.transports.values().flatten()
+    }
+
+    fn teleports(&self) -> &[Teleport] {
+        &self.teleports
+    }
+}
+
+/// Decodes Bladed Dive targets, reading the `i`th word for `(x, y)` with `data(x, y, i)`.
+fn build_bd_map(x_start: usize, x_end: usize, y_start: usize, y_end: usize, data: impl Fn(usize, usize, usize) -> u64) -> TileMap {
+    let mut bd_map = TileMap::with_capacity((x_end - x_start + 1) * (y_end - y_start + 1), 32);
     for x in x_start..=x_end {
         for y in y_start..=y_end {
-            let mut tiles = Vec::new();
             for i in 0..7 {
-                let bd_data = arr[[x - x_start, y - y_start, i]];
+                let bd_data = data(x, y, i);
                 for j in 0..64 {
                     if (bd_data >> j) & 1 == 1 {
-                        let u = x - 10 + (j+64*i) % 21;
-                        let v = y - 10 + (j+64*i) / 21;
+                        let u = x + (j+64*i) % 21 - 10;
+                        let v = y + (j+64*i) / 21 - 10;
                         let x_diff = (u as isize) - (x as isize);
                         let y_diff = (v as isize) - (y as isize);
                         let mut direction: u8 = 0;
@@ -99,13 +336,7 @@ fn build_bd_hashmap(x_start: usize, x_end: usize, y_start: usize, y_end: usize,
                             } else {
                                 direction = 4;
                             }
-                        } else if y_diff == 0 {
-                            if x_diff > 0 {
-                                direction = 2;
-                            } else {
-                                direction = 6;
-                            }
-                        } else if (14 * x_diff.abs() + 7) / (2 * y_diff.abs() + 1) > 15 {
+                        } else if y_diff == 0 || (14 * x_diff.abs() + 7) / (2 * y_diff.abs() + 1) > 15 {
                             if x_diff > 0 {
                                 direction = 2;
                             } else {
@@ -130,106 +361,146 @@ fn build_bd_hashmap(x_start: usize, x_end: usize, y_start: usize, y_end: usize,
                                 direction = 5;
                             }
                         }
-                        tiles.push((u as u16, v as u16, direction));
+                        bd_map.tiles.push((u as u16, v as u16, direction));
                     }
                 }
             }
-            bd_hashmap.insert((x as u16, y as u16), tiles);
+            bd_map.finish_tile();
         }
     }
-    bd_hashmap
+    bd_map
 }
 
-fn build_walk_hashmap(x_start: usize, x_end: usize, y_start: usize, y_end: usize, arr: &Array3<u64>) -> HashMap<(u16, u16), Vec<(u16, u16, u8)>> {
-    let mut walk_hashmap = HashMap::new();
+/// Decodes walk targets, reading the `i`th word for `(x, y)` with `data(x, y, i)`.
+fn build_walk_map(x_start: usize, x_end: usize, y_start: usize, y_end: usize, data: impl Fn(usize, usize, usize) -> u64) -> TileMap {
+    let mut walk_map = TileMap::with_capacity((x_end - x_start + 1) * (y_end - y_start + 1), 8);
     for x in x_start..=x_end {
         for y in y_start..=y_end {
-            let mut tiles = Vec::new();
             for i in 0..2 {
-                let walk_data = arr[[x-x_start, y - y_start, i]];
+                let walk_data = data(x, y, i);
                 for j in 0..16 {
                     let direction = (walk_data >> (j * 4)) & 15;
                     if direction < 8 {
-                        let u = x - 2 + (j + 16 * i) % 5;
-                        let v = y - 2 + (j + 16 * i) / 5;
-                        tiles.push((u as u16, v as u16, direction as u8))
+                        let u = x + (j + 16 * i) % 5 - 2;
+                        let v = y + (j + 16 * i) / 5 - 2;
+                        walk_map.tiles.push((u as u16, v as u16, direction as u8))
                     }
                 }
             }
-            walk_hashmap.insert((x as u16, y as u16), tiles);
+            walk_map.finish_tile();
         }
     }
-    walk_hashmap
+    walk_map
+}
+
+/// One of the arrays of every chunk a rectangle overlaps, indexed by world coordinates. `chunks` holds
+/// rows of chunks, starting with the one containing `(x_start, y_start)`.
+struct ChunkViews<'a, A> {
+    chunk_x: usize,
+    chunk_y: usize,
+    views: Vec<Vec<ArrayView3<'a, A>>>,
 }
 
-fn build_bd_array(x_start: usize, x_end: usize, y_start: usize, y_end: usize, floor: usize) -> Array3<u64> {
-    let chunk_size =  1280;
-    let chunk_x = (x_start/chunk_size, x_end/chunk_size);
-    let chunk_y = (y_start/chunk_size, y_end/chunk_size);
-    let mut rows = Vec::new();
-    for j in chunk_y.0..=chunk_y.1 {
-        let mut row  = Vec::new();
-        for i in chunk_x.0..=chunk_x.1 {
-            let path = format!("MapData/BD/bd-{i}-{j}-{floor}.npy");
-            let arr: Array3<u64> = read_npy(path).unwrap();
-            let x_1 = cmp::max(x_start % chunk_size,(i - chunk_x.0) * chunk_size) - (i - chunk_x.0) * chunk_size;
-            let x_2 = cmp::min(x_end - x_start + (x_start % chunk_size) + 1, chunk_size);
-            let y_1 = cmp::max(y_start % chunk_size, (j - chunk_y.0) * chunk_size) - (j - chunk_y.0) * chunk_size;
-            let y_2 = cmp::min(y_end - y_start + (y_start % chunk_size) - (j - chunk_y.0) * chunk_size + 1, chunk_size);
-            let arr = arr.slice(ndarray::s![x_1..x_2, y_1..y_2, ..]).to_owned();
-            row.push(arr);
+impl<'a, A: Copy> ChunkViews<'a, A> {
+    fn new(x_start: usize, y_start: usize, chunks: &'a [Vec<Arc<Chunk>>], field: impl Fn(&'a Chunk) -> ArrayView3<'a, A>) -> ChunkViews<'a, A> {
+        ChunkViews {
+            chunk_x: x_start / CHUNK_SIZE,
+            chunk_y: y_start / CHUNK_SIZE,
+            views: chunks.iter().map(|row| row.iter().map(|chunk| field(chunk)).collect()).collect(),
         }
-        let views: Vec<_> = row.iter().map(|arr| arr.view()).collect();
-        rows.push(concatenate(Axis(0), &views[..]).unwrap());
     }
-    let views: Vec<_> = rows.iter().map(|arr| arr.view()).collect();
-    concatenate(Axis(1), &views[..]).unwrap()
+
+    fn get(&self, x: usize, y: usize, i: usize) -> A {
+        self.views[y / CHUNK_SIZE - self.chunk_y][x / CHUNK_SIZE - self.chunk_x][[x % CHUNK_SIZE, y % CHUNK_SIZE, i]]
+    }
 }
 
-fn build_se_array(x_start: usize, x_end: usize, y_start: usize, y_end: usize, floor: usize) -> Array3<u8> {
-    let chunk_size =  1280;
-    let chunk_x = (x_start/chunk_size, x_end/chunk_size);
-    let chunk_y = (y_start/chunk_size, y_end/chunk_size);
-    let mut rows = Vec::new();
-    for j in chunk_y.0..=chunk_y.1 {
-        let mut row  = Vec::new();
-        for i in chunk_x.0..=chunk_x.1 {
-            let path = format!("MapData/SE/se-{i}-{j}-{floor}.npy");
-            let arr: Array3<u8> = read_npy(path).unwrap();
-            let x_1 = cmp::max(x_start % chunk_size,(i - chunk_x.0) * chunk_size) - (i - chunk_x.0) * chunk_size;
-            let x_2 = cmp::min(x_end - x_start + (x_start % chunk_size) + 1, chunk_size);
-            let y_1 = cmp::max(y_start % chunk_size, (j - chunk_y.0) * chunk_size) - (j - chunk_y.0) * chunk_size;
-            let y_2 = cmp::min(y_end - y_start + (y_start % chunk_size) - (j - chunk_y.0) * chunk_size + 1, chunk_size);
-            let arr = arr.slice(ndarray::s![x_1..x_2, y_1..y_2, ..]).to_owned();
-            row.push(arr);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn floor_data_decodes_each_tile_into_its_own_range() {
+        let (x_start, x_end, y_start, y_end) = (100, 102, 200, 201);
+        // Every walk nibble set to 15 means no tile is reachable.
+        let mut walk = Array3::from_elem([3, 2, 2], u64::MAX);
+        let mut bd = Array3::zeros([3, 2, 7]);
+        // (101, 200) walks east to (102, 200) and north-west to (99, 202).
+        walk[[1, 0, 0]] &= !(15 << (13 * 4)) | (2 << (13 * 4));
+        walk[[1, 0, 1]] &= !(15 << (4 * 4)) | (7 << (4 * 4));
+        // (101, 201) dives three tiles north-east to (104, 204).
+        bd[[1, 1, 4]] |= 1 << 30;
+        let data = FloorData::new(x_start, x_end, y_start, y_end, walk.view(), bd.view(), Array3::zeros([3, 2, 8]));
+
+        assert_eq!(data.walk_range(101, 200), [(102, 200, 2), (99, 202, 7)]);
+        assert_eq!(data.bd_range(101, 201), [(104, 204, 1)]);
+        for x in 100..=102 {
+            for y in 200..=201 {
+                if (x, y) != (101, 200) {
+                    assert!(data.walk_range(x, y).is_empty(), "({x}, {y})");
+                }
+                if (x, y) != (101, 201) {
+                    assert!(data.bd_range(x, y).is_empty(), "({x}, {y})");
+                }
+            }
         }
-        let views: Vec<_> = row.iter().map(|arr| arr.view()).collect();
-        rows.push(concatenate(Axis(0), &views[..]).unwrap());
     }
-    let views: Vec<_> = rows.iter().map(|arr| arr.view()).collect();
-    concatenate(Axis(1), &views[..]).unwrap()
-}
 
-fn build_walk_array(x_start: usize, x_end: usize, y_start: usize, y_end: usize, floor: usize) -> Array3<u64> {
-    let chunk_size =  1280;
-    let chunk_x = (x_start/chunk_size, x_end/chunk_size);
-    let chunk_y = (y_start/chunk_size, y_end/chunk_size);
-    let mut rows = Vec::new();
-    for j in chunk_y.0..=chunk_y.1 {
-        let mut row  = Vec::new();
-        for i in chunk_x.0..=chunk_x.1 {
-            let path = format!("MapData/Walk/walk-{i}-{j}-{floor}.npy");
-            let arr: Array3<u64> = read_npy(path).unwrap();
-            let x_1 = cmp::max(x_start % chunk_size,(i - chunk_x.0) * chunk_size) - (i - chunk_x.0) * chunk_size;
-            let x_2 = cmp::min(x_end - x_start + (x_start % chunk_size) + 1, chunk_size);
-            let y_1 = cmp::max(y_start % chunk_size, (j - chunk_y.0) * chunk_size) - (j - chunk_y.0) * chunk_size;
-            let y_2 = cmp::min(y_end - y_start + (y_start % chunk_size) - (j - chunk_y.0) * chunk_size + 1, chunk_size);
-            let arr = arr.slice(ndarray::s![x_1..x_2, y_1..y_2, ..]).to_owned();
-            row.push(arr);
+    /// Chunks with no walkable tiles except one tile next to the chunk's western or eastern edge, which
+    /// walks a tile east and surges three.
+    struct EdgeSource;
+
+    impl ChunkSource for EdgeSource {
+        fn chunk(&self, chunk_x: usize, _chunk_y: usize, _floor: usize) -> Result<Arc<Chunk>> {
+            let u = if chunk_x == 0 { CHUNK_SIZE - 1 } else { 0 };
+            let mut walk = Array3::from_elem([CHUNK_SIZE, CHUNK_SIZE, 2], u64::MAX);
+            let mut se = Array3::zeros([CHUNK_SIZE, CHUNK_SIZE, 8]);
+            walk[[u, 5, 0]] &= !(15 << (13 * 4)) | (2 << (13 * 4));
+            se[[u, 5, 2]] = 3;
+            Ok(Arc::new(Chunk::from_arrays(walk, Array3::zeros([CHUNK_SIZE, CHUNK_SIZE, 7]), se)))
         }
-        let views: Vec<_> = row.iter().map(|arr| arr.view()).collect();
-        rows.push(concatenate(Axis(0), &views[..]).unwrap());
     }
-    let views: Vec<_> = rows.iter().map(|arr| arr.view()).collect();
-    concatenate(Axis(1), &views[..]).unwrap()
-}
\ No newline at end of file
+
+    #[test]
+    fn sections_spanning_chunks_read_each_tile_from_its_own_chunk() {
+        let edge = CHUNK_SIZE as u16;
+        let section = MapSection::create_map_section(CHUNK_SIZE - 2, CHUNK_SIZE + 1, 4, 6, 0, &EdgeSource).unwrap();
+
+        for x in edge - 1..=edge {
+            assert_eq!(section.walk_range(x, 5, 0), [(x + 1, 5, 2)]);
+            assert_eq!(section.surge_range(x, 5, 0, 2), (x as usize + 3, 5));
+        }
+        assert!(section.walk_range(edge - 2, 5, 0).is_empty());
+        assert!(section.walk_range(edge + 1, 5, 0).is_empty());
+        assert_eq!(section.surge_range(edge + 1, 5, 0, 2), (edge as usize + 1, 5));
+    }
+
+    #[test]
+    fn transports_load_the_floors_they_lead_to() {
+        use crate::{chunk_cache::tests::FakeSource, goal::Goal,
+                    loadout::{AbilitySet, CooldownProfile, Loadout}, pathfinding::{a_star_end_buffer, Heuristic},
+                    state::State, transport::Player};
+
+        let source = Arc::new(FakeSource::new(CHUNK_SIZE));
+        let mut section = MapSection::create_map_section(0, 19, 0, 19, 0, &source).unwrap();
+        let stairs = Transport::connector((10, 10, 0), (10, 10, 1), 3);
+        // Leads out of the section, so it is left out and its floor stays unloaded.
+        let ladder = Transport::new((12, 12, 0), (40, 40, 2), 1);
+        assert!(!section.is_loaded(10, 10, 1));
+        section.add_transports(stairs.iter().chain([&ladder]), &source).unwrap();
+        assert!(section.is_loaded(10, 10, 1));
+        assert!(!section.is_loaded(12, 12, 2));
+        assert_eq!(section.transports(10, 10, 0), &stairs[..1]);
+        assert_eq!(section.transports(10, 10, 1), &stairs[1..]);
+        assert!(section.transports(12, 12, 0).is_empty());
+
+        // Every tile is blocked, so the stairs are the only move there is.
+        let loadout = Loadout::new(AbilitySet::NONE, CooldownProfile::new(3, 3, 3, 6, 1));
+        let heuristic = Heuristic::generate(20, &loadout);
+        let start = State { pos_x: 10, pos_y: 10, floor: 0, direction: 0, secd: 0, scd: 0, ecd: 0, bdcd: 0, bgcd: 0 };
+        let goal = Goal::Tile { x: 10, y: 10 }.on_floor(1);
+        let result = a_star_end_buffer(start, &goal, &section, &heuristic, &loadout, &Player::default()).unwrap();
+        assert_eq!(result.path.iter().map(|s| s.floor).collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(result.ticks, 3);
+    }
+}
@@ -1,48 +1,494 @@
 use pathfinding::prelude::astar;
 use ndarray::Array5;
-use ndarray_npy::read_npy;
-use std::cmp::max;
-use crate::{map_section::MapSection,
-            state::State};
+use std::{cmp::max, fmt};
+use crate::{data_paths::DataPaths,
+            error::{self, read_array},
+            goal::Goal,
+            loadout::Loadout,
+            map_section::MapView,
+            search::{self, HashedIndex, PackedIndex, Packing},
+            state::{State, BARGE_RANGE},
+            transport::Player};
 
-pub fn a_star_end_buffer(start: State, end: (u16, u16), map: &MapSection, heuristic: Heuristic) -> (Vec<State>, usize) {
-    let result = astar(&start, |s| successors(s, map), |s| heuristic.h(s, end), |s| s.at_goal(&end)).unwrap();
-    result
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathResult {
+    pub path: Vec<State>,
+    pub ticks: usize,
+    /// Index of the goal the path ends in, always 0 for single-goal searches.
+    pub goal: usize,
 }
 
-fn successors(state: &State, map: &MapSection) -> Vec<(State, usize)> {
-    let mut adjacent = Vec::with_capacity(500);
-    for pos in map.walk_range(state.pos_x, state.pos_y) {
-        adjacent.push((state.r#move(pos.0, pos.1, pos.2).update(), 1));
+/// Why a search found no route.
+///
+/// A goal further away than the heuristic table reaches is not an error: past its end the estimate adds
+/// a tick for every stretch of the most tiles one tick can cover, so such routes are searched like any other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathError {
+    /// None of the goals in the map can be reached from the start.
+    NoPath,
+    StartOutsideSection { x: u16, y: u16 },
+    /// No goal has a tile in the map; holds the nearest tile of the first such goal.
+    GoalOutsideSection { x: u16, y: u16 },
+    /// The heuristic was built for a loadout the searched one can outpace, so it could overestimate.
+    IncompatibleHeuristic { heuristic: Loadout, search: Loadout },
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::NoPath => write!(f, "no path exists to the goal"),
+            PathError::StartOutsideSection { x, y } => write!(f, "start tile ({x}, {y}) is outside the map section"),
+            PathError::GoalOutsideSection { x, y } => write!(f, "goal tile ({x}, {y}) is outside the map section"),
+            PathError::IncompatibleHeuristic { heuristic, search } => {
+                write!(f, "heuristic built for {heuristic:?} is not admissible for {search:?}")
+            }
+        }
     }
-    if state.can_bd() {
-        for pos in map.bd_range(state.pos_x, state.pos_y) {
-            adjacent.push((state.bd(pos.0, pos.1, pos.2), 0));
+}
+
+impl std::error::Error for PathError {}
+
+/// Finds the fastest route to `goal`, using only the transports and teleports `player` meets the
+/// requirements of.
+pub fn a_star_end_buffer(start: State, goal: &Goal, map: &impl MapView, heuristic: &Heuristic, loadout: &Loadout, player: &Player) -> Result<PathResult, PathError> {
+    a_star_multi_goal(start, std::slice::from_ref(goal), map, heuristic, loadout, player)
+}
+
+/// Finds the fastest route to whichever of `goals` can be reached first. Goals with no tile in `map` are
+/// skipped.
+pub fn a_star_multi_goal(start: State, goals: &[Goal], map: &impl MapView, heuristic: &Heuristic, loadout: &Loadout, player: &Player) -> Result<PathResult, PathError> {
+    search(start, goals, map, heuristic, loadout, player, Strategy::Packed)
+}
+
+/// Same as `a_star_multi_goal`, but always runs the generic A* of the `pathfinding` crate over unpacked
+/// states, with a new successor list for every expansion. Slower; kept to check the packed search against.
+pub fn a_star_reference(start: State, goals: &[Goal], map: &impl MapView, heuristic: &Heuristic, loadout: &Loadout, player: &Player) -> Result<PathResult, PathError> {
+    search(start, goals, map, heuristic, loadout, player, Strategy::Reference)
+}
+
+/// Same as `a_star_multi_goal`, but keeps whole states even when they could be packed.
+#[cfg(test)]
+pub(crate) fn a_star_hashed(start: State, goals: &[Goal], map: &impl MapView, heuristic: &Heuristic, loadout: &Loadout, player: &Player) -> Result<PathResult, PathError> {
+    search(start, goals, map, heuristic, loadout, player, Strategy::Hashed)
+}
+
+/// Which A* `search` runs.
+#[derive(Debug, Clone, Copy)]
+enum Strategy {
+    /// The `pathfinding` crate's, over whole states.
+    Reference,
+    /// The radix heap one in `search`, over packed states where the loadout allows it.
+    Packed,
+    /// The radix heap one over whole states.
+    #[cfg(test)]
+    Hashed,
+}
+
+fn search(start: State, goals: &[Goal], map: &impl MapView, heuristic: &Heuristic, loadout: &Loadout, player: &Player, strategy: Strategy) -> Result<PathResult, PathError> {
+    if !heuristic.loadout.admits(loadout) {
+        return Err(PathError::IncompatibleHeuristic { heuristic: heuristic.loadout, search: *loadout });
+    }
+    if !map.is_loaded(start.pos_x, start.pos_y, start.floor) {
+        return Err(PathError::StartOutsideSection { x: start.pos_x, y: start.pos_y });
+    }
+    let mut outside = None;
+    let mut reachable = Vec::with_capacity(goals.len());
+    for (i, goal) in goals.iter().enumerate() {
+        if let Some((goal_x, goal_y)) = goal.closest_tile(start.pos_x, start.pos_y) {
+            let floor = goal.floor().unwrap_or(start.floor);
+            if map.is_loaded(goal_x, goal_y, floor) || goal.any_tile(&mut |x, y| map.is_loaded(x, y, floor)) {
+                reachable.push(i);
+            } else {
+                outside.get_or_insert(PathError::GoalOutsideSection { x: goal_x, y: goal_y });
+            }
         }
     }
-    if state.can_surge() {
-        adjacent.push((state.surge(&map), 0));
+    if reachable.is_empty() {
+        return Err(outside.unwrap_or(PathError::NoPath));
     }
-    if state.can_escape() {
-        adjacent.push((state.escape(&map), 0));
+    let goals: Vec<Goal> = reachable.iter().map(|&i| goals[i].clone()).collect();
+    let transport_bound = heuristic.transport_bound(map, player, &goals);
+    let next = |s: &State, adjacent: &mut Vec<(State, usize)>| successors(s, map, loadout, player, &goals, adjacent);
+    let estimate = |s: &State| heuristic.h(s, &goals, transport_bound);
+    let success = |s: &State| s.at_any_goal(&goals);
+    let found = match strategy {
+        Strategy::Reference => astar(&start, |s| {
+            let mut adjacent = Vec::new();
+            next(s, &mut adjacent);
+            adjacent
+        }, estimate, success),
+        // Cooldown profiles too long to pack keep whole states instead.
+        Strategy::Packed => match Packing::new(loadout).filter(|packing| packing.fits(&start)) {
+            Some(packing) => search::astar(&start, PackedIndex::new(packing), next, estimate, success),
+            None => search::astar(&start, HashedIndex::default(), next, estimate, success),
+        },
+        #[cfg(test)]
+        Strategy::Hashed => search::astar(&start, HashedIndex::default(), next, estimate, success),
+    };
+    let (path, ticks) = found.ok_or(PathError::NoPath)?;
+    let last = path.last().unwrap();
+    let goal = reachable[goals.iter().position(|goal| last.at_goal(goal)).unwrap()];
+    Ok(PathResult { path, ticks, goal })
+}
+
+/// Chebyshev distance to the nearest goal on the same floor as `state`.
+fn goal_distance(state: &State, goals: &[Goal]) -> Option<usize> {
+    goals.iter()
+        .filter(|goal| goal.floor().is_none_or(|floor| floor == state.floor))
+        .map(|goal| goal.distance(state.pos_x, state.pos_y))
+        .min()
+}
+
+/// Replaces the contents of `adjacent` with every state reachable from `state` and the ticks it takes, so
+/// one buffer can be reused across a whole search. Transports and teleports `player` does not meet the
+/// requirements of are left out.
+pub fn successors(state: &State, map: &impl MapView, loadout: &Loadout, player: &Player, goals: &[Goal], adjacent: &mut Vec<(State, usize)>) {
+    let (abilities, cooldowns) = (&loadout.abilities, &loadout.cooldowns);
+    adjacent.clear();
+    let updated = state.update();
+    for pos in map.walk_range(state.pos_x, state.pos_y, state.floor) {
+        adjacent.push((updated.r#move(pos.0, pos.1, pos.2), 1));
     }
-    adjacent.push((state.update(), 1));
-    adjacent
+    if abilities.bladed_dive && state.can_bd() {
+        for pos in map.bd_range(state.pos_x, state.pos_y, state.floor) {
+            adjacent.push((state.bd(pos.0, pos.1, pos.2, cooldowns), 0));
+        }
+    }
+    if abilities.barge && state.can_barge() {
+        for target in goals.iter().filter(|goal| goal.floor().is_none_or(|floor| floor == state.floor)).filter_map(Goal::barge_target) {
+            if let Some(pos) = barge_landing(state, map, target) {
+                adjacent.push((state.barge(pos.0, pos.1, pos.2, cooldowns), 0));
+            }
+        }
+    }
+    // A Surge or Escape that goes nowhere, such as into a wall or off the edge of the loaded area, would
+    // only put the ability on cooldown.
+    if abilities.surge && state.can_surge(cooldowns) {
+        let next = state.surge(map, cooldowns);
+        if (next.pos_x, next.pos_y) != (state.pos_x, state.pos_y) {
+            adjacent.push((next, 0));
+        }
+    }
+    if abilities.escape && state.can_escape(cooldowns) {
+        let next = state.escape(map, cooldowns);
+        if (next.pos_x, next.pos_y) != (state.pos_x, state.pos_y) {
+            adjacent.push((next, 0));
+        }
+    }
+    for transport in map.transports(state.pos_x, state.pos_y, state.floor).iter().filter(|t| t.usable_by(player)) {
+        adjacent.push((state.transport(transport), transport.ticks));
+    }
+    for teleport in map.teleports().iter().filter(|t| t.usable_by(player)) {
+        adjacent.push((state.teleport(teleport), teleport.ticks));
+    }
+    adjacent.push((updated, 1));
+    adjacent.retain(|(next, _)| map.is_loaded(next.pos_x, next.pos_y, next.floor));
+}
+
+/// Barge at an NPC standing on `end` stops on the closest reachable tile next to it.
+fn barge_landing(state: &State, map: &impl MapView, end: (u16, u16)) -> Option<(u16, u16, u8)> {
+    if max(state.pos_x.abs_diff(end.0), state.pos_y.abs_diff(end.1)) > BARGE_RANGE {
+        return None;
+    }
+    map.bd_range(state.pos_x, state.pos_y, state.floor).iter()
+        .filter(|pos| (pos.0, pos.1) != end && max(pos.0.abs_diff(end.0), pos.1.abs_diff(end.1)) <= 1)
+        .min_by_key(|pos| max(pos.0.abs_diff(state.pos_x), pos.1.abs_diff(state.pos_y)))
+        .copied()
 }
 
 pub struct Heuristic {
     data: Array5<u64>,
+    /// Table for the loadout without Barge, used while Barge is on cooldown. Only loaded for loadouts with
+    /// Barge.
+    without_barge: Option<Array5<u64>>,
+    loadout: Loadout,
+    /// Most tiles the loadout can cover in one tick, for distances past the end of the tables.
+    tiles_per_tick: usize,
 }
 
 impl Heuristic {
-    pub fn new() -> Heuristic{
-        let data: Array5<u64> = read_npy("HeuristicData/l_infinity_cds.npy").unwrap();
+    pub fn new(paths: &DataPaths, loadout: &Loadout) -> error::Result<Heuristic> {
+        let data: Array5<u64> = read_array(&paths.heuristic(loadout), &loadout.heuristic_shape())?;
+        let without_barge = if loadout.abilities.barge {
+            let loadout = loadout.without_barge();
+            Some(read_array(&paths.heuristic(&loadout), &loadout.heuristic_shape())?)
+        } else {
+            None
+        };
+        Ok(Heuristic {
+            data,
+            without_barge,
+            loadout: *loadout,
+            tiles_per_tick: tiles_per_tick(loadout),
+        })
+    }
+
+    #[cfg(test)]
+    pub(crate) fn generate(max_distance: usize, loadout: &Loadout) -> Heuristic {
+        use crate::preprocessing::heuristic_table;
         Heuristic {
-            data
+            data: heuristic_table(max_distance, loadout),
+            without_barge: loadout.abilities.barge.then(|| heuristic_table(max_distance, &loadout.without_barge())),
+            loadout: *loadout,
+            tiles_per_tick: tiles_per_tick(loadout),
+        }
+    }
+
+    pub fn loadout(&self) -> &Loadout {
+        &self.loadout
+    }
+
+    pub fn max_distance(&self) -> usize {
+        self.data.shape()[0] - 1
+    }
+
+    /// Lower bound on the ticks to reach the nearest of `goals`. `transport_bound` comes from
+    /// `transport_bound` for the same goals and covers routes that change tile through a transport.
+    pub fn h(&self, state: &State, goals: &[Goal], transport_bound: Option<usize>) -> usize{
+        let direct = goal_distance(state, goals)
+            .map(|distance| self.estimate(distance, [state.secd, state.scd, state.ecd, state.bdcd], state.bgcd));
+        match (direct, transport_bound) {
+            (Some(direct), Some(bound)) => direct.min(bound),
+            (Some(estimate), None) | (None, Some(estimate)) => estimate,
+            (None, None) => 0,
+        }
+    }
+
+    /// Lower bound on any route whose last transport or teleport is one of those in `map` that `player`
+    /// can use: its own ticks plus the fastest possible finish from where it lands. `None` if none of them
+    /// lands near a goal.
+    pub fn transport_bound(&self, map: &impl MapView, player: &Player, goals: &[Goal]) -> Option<usize> {
+        let transports = map.all_transports().filter(|t| t.usable_by(player)).map(|t| (t.to_x, t.to_y, t.to_floor, t.ticks));
+        let teleports = map.teleports().iter().filter(|t| t.usable_by(player)).map(|t| (t.to_x, t.to_y, t.to_floor, t.ticks));
+        transports.chain(teleports)
+            .filter_map(|(x, y, floor, ticks)| {
+                let landing = State {
+                    pos_x: x,
+                    pos_y: y,
+                    floor,
+                    direction: 0,
+                    secd: 0,
+                    scd: 0,
+                    ecd: 0,
+                    bdcd: 0,
+                    bgcd: 0,
+                };
+                goal_distance(&landing, goals).map(|distance| ticks + self.estimate(distance, [0; 4], 0))
+            })
+            .min()
+    }
+
+    fn estimate(&self, distance: usize, cooldowns: [u8; 4], bgcd: u8) -> usize {
+        let ready = self.lookup(&self.data, distance, cooldowns);
+        match &self.without_barge {
+            // A route that still barges has to wait out its cooldown first.
+            Some(without_barge) if bgcd > 0 => self.lookup(without_barge, distance, cooldowns).min(ready.max(bgcd as usize)),
+            _ => ready,
         }
     }
-    pub fn h(&self, state: &State, end: (u16, u16)) -> usize{
-        let distance = (max(state.pos_x.abs_diff(end.0), state.pos_y.abs_diff(end.1)) - 1) as usize;
-        self.data[[distance, state.secd as usize, state.scd as usize, state.ecd as usize, state.bdcd as usize]] as usize
+
+    /// Past the end of the table, a route first has to cover the table's last distance, and then the rest
+    /// at no more than `tiles_per_tick` a tick. Counting whole ticks only for the rest allows for the tick
+    /// that crosses the boundary covering some of both.
+    fn lookup(&self, data: &Array5<u64>, distance: usize, cooldowns: [u8; 4]) -> usize {
+        let max_distance = data.shape()[0] - 1;
+        table_value(data, distance.min(max_distance), cooldowns) + distance.saturating_sub(max_distance) / self.tiles_per_tick
+    }
+}
+
+/// Tiles a single tick can move the player with every ability in `loadout` used, each with all its
+/// charges, on top of running two tiles.
+fn tiles_per_tick(loadout: &Loadout) -> usize {
+    let (abilities, cooldowns) = (&loadout.abilities, &loadout.cooldowns);
+    let mut tiles = 2;
+    if abilities.surge {
+        tiles += 10 * cooldowns.surge_charges() as usize;
+    }
+    if abilities.escape {
+        tiles += 7 * cooldowns.escape_charges() as usize;
+    }
+    if abilities.bladed_dive {
+        tiles += 10;
+    }
+    if abilities.barge {
+        tiles += BARGE_RANGE as usize;
+    }
+    tiles
+}
+
+fn table_value(data: &Array5<u64>, distance: usize, cooldowns: [u8; 4]) -> usize {
+    // Clamping cooldowns down can only lower the estimate, so it stays admissible.
+    let shape = data.shape();
+    let secd = (cooldowns[0] as usize).min(shape[1] - 1);
+    let scd = (cooldowns[1] as usize).min(shape[2] - 1);
+    let ecd = (cooldowns[2] as usize).min(shape[3] - 1);
+    let bdcd = (cooldowns[3] as usize).min(shape[4] - 1);
+    data[[distance, secd, scd, ecd, bdcd]] as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{loadout::{AbilitySet, CooldownProfile},
+                test_map::GridMap,
+                transport::{Requirement, Teleport, Transport}};
+
+    fn state_at(x: u16, y: u16) -> State {
+        State { pos_x: x, pos_y: y, floor: 0, direction: 0, secd: 0, scd: 0, ecd: 0, bdcd: 0, bgcd: 0 }
+    }
+
+    fn barge_only() -> Loadout {
+        Loadout::new(AbilitySet { barge: true, ..AbilitySet::NONE }, CooldownProfile::new(3, 3, 3, 6, 1))
+    }
+
+    #[test]
+    fn barge_aims_at_the_given_target_rather_than_the_goal() {
+        let map = GridMap::new(30, 30, 1, &[]);
+        let goal = Goal::Tile { x: 25, y: 25 }.with_barge_target(12, 5);
+        let mut adjacent = Vec::new();
+        successors(&state_at(5, 5), &map, &barge_only(), &Player::default(), &[goal], &mut adjacent);
+        let barged: Vec<_> = adjacent.iter().filter(|(next, _)| next.bgcd > 0).collect();
+        assert_eq!(barged.len(), 1);
+        let (landing, ticks) = &barged[0];
+        assert_eq!((landing.pos_x, *ticks), (11, 0));
+        assert!(landing.pos_y.abs_diff(5) <= 1);
+    }
+
+    #[test]
+    fn heuristic_only_counts_barge_once_it_is_ready() {
+        let heuristic = Heuristic::generate(20, &barge_only());
+        let goals = [Goal::Tile { x: 10, y: 5 }];
+        let h = |bgcd| heuristic.h(&State { bgcd, ..state_at(5, 5) }, &goals, None);
+        assert_eq!(h(0), 0);
+        assert_eq!(h(2), 2);
+        // Walking the 5 tiles takes 3 ticks, sooner than Barge comes back.
+        assert_eq!(h(5), 3);
+    }
+
+    #[test]
+    fn goal_with_only_its_nearest_tile_outside_the_map_is_searched() {
+        let map = GridMap::new(20, 20, 1, &[]);
+        let loadout = Loadout::new(AbilitySet::NONE, CooldownProfile::new(3, 3, 3, 6, 1));
+        let heuristic = Heuristic::generate(20, &loadout);
+        let goal = Goal::Tiles([(25, 2), (5, 18)].into());
+        let result = a_star_end_buffer(state_at(15, 2), &goal, &map, &heuristic, &loadout, &Player::default()).unwrap();
+        let last = result.path.last().unwrap();
+        assert_eq!((last.pos_x, last.pos_y), (5, 18));
+
+        let outside = Goal::rect(25, 2, 30, 18);
+        assert_eq!(a_star_end_buffer(state_at(15, 2), &outside, &map, &heuristic, &loadout, &Player::default()),
+                   Err(PathError::GoalOutsideSection { x: 25, y: 2 }));
+    }
+
+    #[test]
+    fn goal_index_refers_to_the_callers_goals() {
+        let map = GridMap::new(20, 20, 1, &[]);
+        let loadout = Loadout::new(AbilitySet::NONE, CooldownProfile::new(3, 3, 3, 6, 1));
+        let heuristic = Heuristic::generate(20, &loadout);
+        // The first goal lies off the map and the last on a floor it does not have, so only the middle two
+        // are searched, and the nearer of those is at index 2.
+        let goals = [Goal::Tile { x: 50, y: 50 }, Goal::Tile { x: 18, y: 18 }, Goal::Tile { x: 6, y: 6 },
+                     Goal::Tile { x: 3, y: 3 }.on_floor(2)];
+        let player = Player::default();
+
+        for result in [a_star_multi_goal(state_at(2, 2), &goals, &map, &heuristic, &loadout, &player),
+                       a_star_reference(state_at(2, 2), &goals, &map, &heuristic, &loadout, &player),
+                       a_star_hashed(state_at(2, 2), &goals, &map, &heuristic, &loadout, &player)] {
+            let result = result.unwrap();
+            assert_eq!(result.goal, 2);
+            let last = result.path.last().unwrap();
+            assert_eq!((last.pos_x, last.pos_y), (6, 6));
+        }
+    }
+
+    #[test]
+    fn goals_on_another_floor_are_reached_through_a_connector() {
+        // Two floors with no other way between them than the stairs at (5, 5).
+        let map = GridMap::new(20, 20, 2, &[]).with_transports(Transport::connector((5, 5, 0), (5, 5, 1), 3).to_vec());
+        let loadout = Loadout::new(AbilitySet::NONE, CooldownProfile::new(3, 3, 3, 6, 1));
+        let heuristic = Heuristic::generate(20, &loadout);
+        let goal = Goal::Tile { x: 15, y: 15 }.on_floor(1);
+
+        let result = a_star_end_buffer(state_at(2, 2), &goal, &map, &heuristic, &loadout, &Player::default()).unwrap();
+        let last = result.path.last().unwrap();
+        assert_eq!((last.pos_x, last.pos_y, last.floor), (15, 15, 1));
+        let climb = result.path.windows(2).position(|w| w[0].floor != w[1].floor).unwrap();
+        let (before, after) = (&result.path[climb], &result.path[climb + 1]);
+        assert_eq!((before.pos_x, before.pos_y, before.floor), (5, 5, 0));
+        assert_eq!((after.pos_x, after.pos_y, after.floor), (5, 5, 1));
+        assert!(result.path[climb + 1..].iter().all(|s| s.floor == 1));
+        // Running covers two tiles a tick: 2 ticks to the stairs, 3 to climb, 5 more to the goal.
+        assert_eq!(result.ticks, 2 + 3 + 5);
+    }
+
+    #[test]
+    fn transports_the_player_cannot_use_are_skipped() {
+        let mut shortcut = Transport::new((2, 2, 0), (2, 17, 0), 1);
+        shortcut.requirements.push(Requirement::Level { skill: "agility".to_string(), level: 60 });
+        // A wall across the map, so walking round takes far longer than the shortcut.
+        let walls: Vec<_> = (0..19).map(|x| (x, 10, 0)).collect();
+        let map = GridMap::new(20, 20, 1, &walls).with_transports(vec![shortcut]);
+        let loadout = Loadout::new(AbilitySet::NONE, CooldownProfile::new(3, 3, 3, 6, 1));
+        let heuristic = Heuristic::generate(20, &loadout);
+        let goal = Goal::Tile { x: 2, y: 17 };
+
+        let mut player = Player::default();
+        let walked = a_star_end_buffer(state_at(2, 2), &goal, &map, &heuristic, &loadout, &player).unwrap();
+        assert!(walked.ticks > 1);
+        player.levels.insert("agility".to_string(), 60);
+        let shortcut = a_star_end_buffer(state_at(2, 2), &goal, &map, &heuristic, &loadout, &player).unwrap();
+        assert_eq!(shortcut.ticks, 1);
+    }
+
+    #[test]
+    fn teleports_the_player_cannot_use_are_skipped() {
+        let mut teleport = Teleport::new("Tablet", (35, 2, 0), 3);
+        teleport.requirements.push(Requirement::Quest("Tablets".to_string()));
+        let map = GridMap::new(40, 5, 1, &[]).with_teleports(vec![teleport]);
+        let loadout = Loadout::new(AbilitySet::NONE, CooldownProfile::new(3, 3, 3, 6, 1));
+        let heuristic = Heuristic::generate(40, &loadout);
+        let goal = Goal::Tile { x: 35, y: 2 };
+
+        let mut player = Player::default();
+        assert_eq!(heuristic.transport_bound(&map, &player, std::slice::from_ref(&goal)), None);
+        let walked = a_star_end_buffer(state_at(2, 2), &goal, &map, &heuristic, &loadout, &player).unwrap();
+        assert_eq!(walked.ticks, 17);
+        player.quests.insert("Tablets".to_string());
+        assert_eq!(heuristic.transport_bound(&map, &player, std::slice::from_ref(&goal)), Some(3));
+        let teleported = a_star_end_buffer(state_at(2, 2), &goal, &map, &heuristic, &loadout, &player).unwrap();
+        assert_eq!(teleported.ticks, 3);
+    }
+
+    #[test]
+    fn heuristic_extends_past_the_end_of_its_table() {
+        let loadout = Loadout::new(AbilitySet::NONE, CooldownProfile::new(3, 3, 3, 6, 1));
+        let heuristic = Heuristic::generate(10, &loadout);
+        let h = |x| heuristic.h(&state_at(0, 0), &[Goal::Tile { x, y: 0 }], None);
+        // Walking covers two tiles a tick, which is also the most a tick can cover without abilities.
+        assert_eq!([h(9), h(10), h(11), h(30), h(31)], [5, 5, 5, 15, 15]);
+    }
+
+    #[test]
+    fn goals_further_than_the_heuristic_table_are_searched() {
+        let map = GridMap::new(600, 3, 1, &[]);
+        let loadout = Loadout::new(AbilitySet::NONE, CooldownProfile::new(3, 3, 3, 6, 1));
+        let heuristic = Heuristic::generate(10, &loadout);
+        let goal = Goal::Tile { x: 595, y: 1 };
+        let result = a_star_end_buffer(state_at(2, 1), &goal, &map, &heuristic, &loadout, &Player::default()).unwrap();
+        assert_eq!(result.ticks, 297);
+        assert_eq!(result.path.last().map(|s| (s.pos_x, s.pos_y)), Some((595, 1)));
+    }
+
+    #[test]
+    fn surge_and_escape_that_go_nowhere_are_dropped() {
+        let map = GridMap::new(20, 20, 1, &[]);
+        let loadout = Loadout::new(AbilitySet { surge: true, escape: true, ..AbilitySet::NONE }, CooldownProfile::new(3, 3, 3, 6, 1));
+        let mut adjacent = Vec::new();
+        let moved_by_ability = |adjacent: &Vec<(State, usize)>| adjacent.iter().filter(|(next, _)| next.secd > 0).count();
+        // Facing the east edge: Surge would stay put, Escape still goes west.
+        successors(&State { direction: 2, ..state_at(19, 5) }, &map, &loadout, &Player::default(), &[], &mut adjacent);
+        assert_eq!(moved_by_ability(&adjacent), 1);
+        successors(&State { direction: 2, ..state_at(10, 5) }, &map, &loadout, &Player::default(), &[], &mut adjacent);
+        assert_eq!(moved_by_ability(&adjacent), 2);
     }
 }
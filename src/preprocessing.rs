@@ -1,25 +1,66 @@
 use std::{collections::{HashSet, HashMap, VecDeque},
           cmp::{max, min},
-          path::Path,
-          fs};
+          fs::{self, File},
+          sync::{atomic::{AtomicUsize, Ordering}, Mutex, OnceLock},
+          thread};
 use zune_inflate::DeflateDecoder;
-use ndarray::{Array1, Array2, Array3, Array5, ShapeBuilder};
-use ndarray_npy::{read_npy, write_npy};
-use indicatif::ProgressBar;
-use crate::util::{adj_positions, free_direction};
+use memmap2::Mmap;
+use ndarray::{Array2, Array3, ArrayView3, Array5, ShapeBuilder};
+use ndarray_npy::{read_npy, write_npy, ViewNpyExt};
+use indicatif::{ProgressBar, ProgressStyle};
+use crate::{data_paths::DataPaths,
+            loadout::Loadout,
+            manifest::{Manifest, SourceHashes, Stage},
+            state::BARGE_RANGE,
+            util::{adj_positions, free_direction, CHUNK_SIZE, FLOORS, RS_HEIGHT, RS_LENGTH}};
 
-const RS_HEIGHT: usize = 12800;
-const RS_LENGTH: usize = 6400;
+const CHUNKS_X: usize = RS_LENGTH / CHUNK_SIZE;
+const CHUNKS_Y: usize = RS_HEIGHT / CHUNK_SIZE;
 
-struct Process {
-    movement_data: HashMap<(usize, usize, usize), Array2<u8>>,
-    bd_data: HashMap<(usize, usize, usize), Array3<u64>>
+/// Movement chunks and Bladed Dive chunks shared read-only between worker threads, each read from disk
+/// the first time any worker needs it. Bladed Dive chunks are mapped rather than read, since the
+/// Surge/Escape stage needs every one of them.
+struct SharedData<'a> {
+    paths: &'a DataPaths,
+    movement: Vec<OnceLock<Array2<u8>>>,
+    bd: Vec<OnceLock<Mmap>>,
 }
 
-impl Process {
-    fn new() -> Process {
+impl<'a> SharedData<'a> {
+    fn new(paths: &'a DataPaths) -> SharedData<'a> {
+        SharedData {
+            paths,
+            movement: (0..CHUNKS_X * CHUNKS_Y * FLOORS).map(|_| OnceLock::new()).collect(),
+            bd: (0..CHUNKS_X * CHUNKS_Y * FLOORS).map(|_| OnceLock::new()).collect(),
+        }
+    }
+
+    fn movement(&self, chunk_x: usize, chunk_y: usize, floor: usize) -> &Array2<u8> {
+        self.movement[(floor * CHUNKS_Y + chunk_y) * CHUNKS_X + chunk_x]
+            .get_or_init(|| read_npy(self.paths.movement(chunk_x, chunk_y, floor)).unwrap())
+    }
+
+    /// Only the Surge/Escape stage reads Bladed Dive data, once the BD stage has finished writing it.
+    fn bd(&self, chunk_x: usize, chunk_y: usize, floor: usize) -> ArrayView3<'_, u64> {
+        let map = self.bd[(floor * CHUNKS_Y + chunk_y) * CHUNKS_X + chunk_x].get_or_init(|| {
+            let file = File::open(self.paths.bd(chunk_x, chunk_y, floor)).unwrap();
+            // Safety: the BD stage has written every file by now, and nothing writes them again before
+            // `setup` returns.
+            unsafe { Mmap::map(&file) }.unwrap()
+        });
+        ArrayView3::view_npy(map).unwrap()
+    }
+}
+
+struct Process<'a> {
+    shared: &'a SharedData<'a>,
+    bd_data: HashMap<(usize, usize, usize), ArrayView3<'a, u64>>
+}
+
+impl<'a> Process<'a> {
+    fn new(shared: &'a SharedData<'a>) -> Process<'a> {
         Process {
-            movement_data: HashMap::new(),
+            shared,
             bd_data: HashMap::new()
         }
     }
@@ -43,12 +84,10 @@ impl Process {
             let current = queue.pop_front().unwrap();
             let current_move_data = self.get_movement_data(current.0, current.1, floor);
             let temp_adj = adj_positions(current.0, current.1);
-            for i in 0..8 {
-                if free_direction(current_move_data, i) {
-                    if !visited.contains(&temp_adj[i]) {
-                        tiles.push((temp_adj[i].0, temp_adj[i].1, i));
-                        visited.insert(temp_adj[i]);
-                    }
+            for (i, &adj) in temp_adj.iter().enumerate() {
+                if free_direction(current_move_data, i) && !visited.contains(&adj) {
+                    tiles.push((adj.0, adj.1, i));
+                    visited.insert(adj);
                 }
             }
         }
@@ -64,6 +103,7 @@ impl Process {
         set.into_iter().collect()
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn bd_range_recursion(&mut self, x: usize, y: usize, floor: usize, direction: usize, horizontal: usize, vertical: usize, dist_x: usize, dist_y: usize, tiles: &mut HashSet<(usize, usize)>) {
         let mut dist_x = dist_x;
         let mut dist_y = dist_y;
@@ -118,7 +158,7 @@ impl Process {
         }
         for i in 0..10 {
             current += d_x + d_y * 21;
-            if (bd_data[[current as usize / 64]] >> (current % 64)) & 1 == 1 {
+            if (bd_data[current as usize / 64] >> (current % 64)) & 1 == 1 {
                 offset = 1 + i;
             }
         }
@@ -144,44 +184,32 @@ impl Process {
         }
         for i in 0..7 {
             current += d_x + d_y * 21;
-            if (bd_data[[current as usize / 64]] >> (current % 64)) & 1 == 1 {
+            if (bd_data[current as usize / 64] >> (current % 64)) & 1 == 1 {
                 offset = 1 + i;
             }
         }
         offset
     }
 
-    fn get_bd_data(&mut self, x: usize, y: usize, floor: usize) -> Array1<u64> {
+    fn get_bd_data(&mut self, x: usize, y: usize, floor: usize) -> [u64; 7] {
+        let mut result = [0; 7];
         if x < RS_LENGTH && y < RS_HEIGHT {
             let chunk_size = 1280;
             let (chunk_x, chunk_y) = (x / chunk_size, y / chunk_size);
-            if let Some(data) = self.bd_data.get(&(chunk_x, chunk_y, floor)) {
-                data.slice(ndarray::s![x % chunk_size, y % chunk_size, ..]).to_owned()
-            } else {
-                let path = format!("MapData/BD/bd-{chunk_x}-{chunk_y}-{floor}.npy");
-                let data: Array3<u64> = read_npy(path).unwrap();
-                let result = data.slice(ndarray::s![x % chunk_size, y % chunk_size, ..]).to_owned();
-                self.bd_data.insert((chunk_x, chunk_y, floor), data);
-                result
+            let shared = self.shared;
+            let data = self.bd_data.entry((chunk_x, chunk_y, floor)).or_insert_with(|| shared.bd(chunk_x, chunk_y, floor));
+            for (i, value) in result.iter_mut().enumerate() {
+                *value = data[[x % chunk_size, y % chunk_size, i]];
             }
-        } else {
-            Array1::from(vec![0,0,0,0,0,0,0])
         }
+        result
     }
 
     fn get_movement_data(&mut self, x: usize, y: usize, floor: usize) -> u8 {
         if x < RS_LENGTH && y < RS_HEIGHT {
             let chunk_size = 1280;
             let (chunk_x, chunk_y) = (x / chunk_size, y / chunk_size);
-            if let Some(data) = self.movement_data.get(&(chunk_x, chunk_y, floor)) {
-                data[[x % chunk_size, y % chunk_size]]
-            } else {
-                let path = format!("MapData/Move/move-{chunk_x}-{chunk_y}-{floor}.npy");
-                let data: Array2<u8> = read_npy(path).unwrap();
-                let result = data[[x % chunk_size, y % chunk_size]];
-                self.movement_data.insert((chunk_x, chunk_y, floor), data);
-                result
-            }
+            self.shared.movement(chunk_x, chunk_y, floor)[[x % chunk_size, y % chunk_size]]
         } else {
             0
         }
@@ -195,7 +223,7 @@ impl Process {
             let v = y - 2;
             if tile.0 < RS_LENGTH && tile.1 < RS_HEIGHT {
                 let temp = (15 - tile.2 as u128) << (4*(tile.0 - u + (tile.1 - v)*5));
-                walk_data = walk_data - temp;
+                walk_data -= temp;
             }
         }
         (walk_data as u64, (walk_data >> 64) as u64)
@@ -218,30 +246,34 @@ impl Process {
     }
 }
 
-fn build_movement_array(chunk_x: usize, chunk_y: usize, floor: usize) -> Array2<u8> {
-    let path = format!("SourceData/collision-{chunk_x}-{chunk_y}-{floor}.bin");
+fn build_movement_array(chunk_x: usize, chunk_y: usize, floor: usize, paths: &DataPaths) -> Array2<u8> {
+    let path = paths.collision(chunk_x, chunk_y, floor);
     let data = fs::read(path).unwrap();
     let mut decoder = DeflateDecoder::new(&data);
     let decompressed_data = decoder.decode_zlib().unwrap();
     Array2::from_shape_vec((1280, 1280).f(), decompressed_data).unwrap()
 }
 
-fn process_movement_data(progress_bar: &ProgressBar) {
-    for i in 0..5 {
-        for j in 0..10 {
-            for k in 0..4 {
-                progress_bar.inc(1);
-                let arr = build_movement_array(i, j ,k);
-                let path = format!("MapData/Move/move-{i}-{j}-{k}.npy");
-                write_npy(path, &arr).unwrap();
-            }
+/// Runs `task` on each of `chunks`, spread over `workers` threads and started in the order given. Each
+/// chunk's output depends only on files written by earlier stages, so it is byte for byte the same however
+/// many workers there are.
+fn for_each_chunk(progress_bar: &ProgressBar, workers: usize, chunks: &[(usize, usize, usize)], task: impl Fn(usize, usize, usize) + Sync) {
+    let next = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        for _ in 0..workers.clamp(1, chunks.len().max(1)) {
+            scope.spawn(|| {
+                while let Some(&(i, j, k)) = chunks.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    task(i, j, k);
+                    progress_bar.inc(1);
+                }
+            });
         }
-    }
+    });
 }
 
-fn build_walk_array(chunk_x: usize, chunk_y: usize, floor: usize) -> Array3<u64> {
+fn build_walk_array(chunk_x: usize, chunk_y: usize, floor: usize, shared: &SharedData) -> Array3<u64> {
     let chunk_size = 1280;
-    let mut process = Process::new();
+    let mut process = Process::new(shared);
     let mut walk_array = Array3::zeros([chunk_size, chunk_size, 2]);
     let start_x = chunk_x * chunk_size;
     let start_y = chunk_y * chunk_size;
@@ -255,22 +287,9 @@ fn build_walk_array(chunk_x: usize, chunk_y: usize, floor: usize) -> Array3<u64>
     walk_array
 }
 
-fn process_walk_data(progress_bar: &ProgressBar) {
-    for i in 0..5 {
-        for j in 0..10 {
-            for k in 0..4 {
-                progress_bar.inc(1);
-                let arr = build_walk_array(i, j ,k);
-                let path = format!("MapData/Walk/walk-{i}-{j}-{k}.npy");
-                write_npy(path, &arr).unwrap();
-            }
-        }
-    }
-}
-
-fn build_bd_array(chunk_x: usize, chunk_y: usize, floor: usize) -> Array3<u64> {
+fn build_bd_array(chunk_x: usize, chunk_y: usize, floor: usize, shared: &SharedData) -> Array3<u64> {
     let chunk_size = 1280;
-    let mut process = Process::new();
+    let mut process = Process::new(shared);
     let mut bd_array = Array3::zeros([chunk_size, chunk_size, 7]);
     let start_x = chunk_x * chunk_size;
     let start_y = chunk_y * chunk_size;
@@ -285,22 +304,9 @@ fn build_bd_array(chunk_x: usize, chunk_y: usize, floor: usize) -> Array3<u64> {
     bd_array
 }
 
-fn process_bd_data(progress_bar: &ProgressBar) {
-    for i in 0..5 {
-        for j in 0..10 {
-            for k in 0..4 {
-                progress_bar.inc(1);
-                let arr = build_bd_array(i, j ,k);
-                let path = format!("MapData/BD/bd-{i}-{j}-{k}.npy");
-                write_npy(path, &arr).unwrap();
-            }
-        }
-    }
-}
-
-fn build_se_array(chunk_x: usize, chunk_y: usize, floor: usize) -> Array3<u8> {
+fn build_se_array(chunk_x: usize, chunk_y: usize, floor: usize, shared: &SharedData) -> Array3<u8> {
     let chunk_size = 1280;
-    let mut process = Process::new();
+    let mut process = Process::new(shared);
     let mut se_array = Array3::zeros([chunk_size, chunk_size, 8]);
     let start_x = chunk_x * chunk_size;
     let start_y = chunk_y * chunk_size;
@@ -316,43 +322,56 @@ fn build_se_array(chunk_x: usize, chunk_y: usize, floor: usize) -> Array3<u8> {
     se_array
 }
 
-fn process_se_data(progress_bar: &ProgressBar) {
-    for i in 0..5 {
-        for j in 0..10 {
-            for k in 0..4 {
-                progress_bar.inc(1);
-                let arr = build_se_array(i, j ,k);
-                let path = format!("MapData/SE/se-{i}-{j}-{k}.npy");
-                write_npy(path, &arr).unwrap();
-            }
-        }
-    }
+/// Regenerates `chunks` of `stage`, noting each in the manifest as soon as its file is written so an
+/// interrupted run only has to redo the chunks it had not finished.
+fn process_stage(progress_bar: &ProgressBar, stage: Stage, chunks: &[(usize, usize, usize)], shared: &SharedData, sources: &SourceHashes, manifest: &Mutex<Manifest>, workers: usize) {
+    let paths = shared.paths;
+    for_each_chunk(progress_bar, workers, chunks, |i, j, k| {
+        let path = stage.output(paths, i, j, k);
+        match stage {
+            Stage::Move => write_npy(path, &build_movement_array(i, j, k, paths)),
+            Stage::Walk => write_npy(path, &build_walk_array(i, j, k, shared)),
+            Stage::Bd => write_npy(path, &build_bd_array(i, j, k, shared)),
+            Stage::Se => write_npy(path, &build_se_array(i, j, k, shared)),
+            Stage::Packed => unreachable!("packed chunks are written by `chunk_format::convert`"),
+        }.unwrap();
+        manifest.lock().unwrap().record(stage, i, j, k, sources.inputs(stage, i, j, k), paths).unwrap();
+    });
+}
+
+fn process_heuristic_data(max_distance: usize, loadout: &Loadout, paths: &DataPaths) {
+    write_npy(paths.heuristic(loadout), &heuristic_table(max_distance, loadout)).unwrap();
 }
 
-fn process_heuristic_data(max_distance: usize) {
-    let mut arr : Array5<u64> = Array5::zeros([max_distance+1, 18, 18, 18, 18]);
-    let mut memo = Memo::new();
+/// Fewest ticks to cover each distance from each combination of cooldowns, indexed as
+/// `[distance, secd, scd, ecd, bdcd]`.
+pub(crate) fn heuristic_table(max_distance: usize, loadout: &Loadout) -> Array5<u64> {
+    let [secd_max, scd_max, ecd_max, bdcd_max] = loadout.cooldowns.max_cooldowns().map(|cd| cd as usize);
+    let mut arr : Array5<u64> = Array5::zeros([max_distance+1, secd_max+1, scd_max+1, ecd_max+1, bdcd_max+1]);
+    let mut memo = Memo::new(loadout);
     for distance in 0..=max_distance {
-        for secd in 0..=17 {
-            for scd in 0..=17 {
-                for ecd in 0..=17 {
-                    for bdcd in 0..=17 {
+        for secd in 0..=secd_max {
+            for scd in 0..=scd_max {
+                for ecd in 0..=ecd_max {
+                    for bdcd in 0..=bdcd_max {
                         arr[[distance, secd, scd, ecd, bdcd]] = memo.distance_cds_rec(distance as isize, secd, scd, ecd, bdcd) as u64;
                     }
                 }
             }
         }
     }
-    write_npy("HeuristicData/l_infinity_cds.npy", &arr).unwrap();
+    arr
 }
 
 struct Memo {
+    loadout: Loadout,
     data: HashMap<(isize, usize, usize, usize, usize), usize>
 }
 
 impl Memo {
-    fn new() -> Memo {
+    fn new(loadout: &Loadout) -> Memo {
         Memo {
+            loadout: *loadout,
             data: HashMap::new()
         }
     }
@@ -368,108 +387,204 @@ impl Memo {
         let mut surge = usize::MAX;
         let mut escape = usize::MAX;
         let mut walk = usize::MAX;
-        if bdcd == 0 {
-            bd = self.distance_cds_rec(distance - 10, secd, scd, ecd, 17);
-        }
-        if secd == 0 {
-            surge = self.distance_cds_rec(distance - 10, 17, max(2, scd), 17, bdcd);
-        } else if scd == 0 {
-            surge = self.distance_cds_rec(distance - 10, max(2, secd), 17, max(2, ecd), bdcd);
-        }
-        if secd == 0 {
-            escape = self.distance_cds_rec(distance - 7, 17, 17, max(2, ecd), bdcd);
-        } else if ecd == 0 {
-            escape = self.distance_cds_rec(distance - 7, max(2, secd), max(2, scd), 17, bdcd);
+        let mut barge = usize::MAX;
+        let abilities = self.loadout.abilities;
+        let cooldowns = self.loadout.cooldowns;
+        let (surge_cd, escape_cd, bd_cd, lockout) = (cooldowns.surge() as usize, cooldowns.escape() as usize, cooldowns.bladed_dive() as usize, cooldowns.lockout() as usize);
+        // Barge lands next to a target on the goal, so it finishes the route from anywhere in range. Its
+        // cooldown is not tracked here, so the table assumes it is ready; `Heuristic` switches to the table
+        // without Barge while it is not.
+        if abilities.barge && distance < BARGE_RANGE as isize {
+            barge = 0;
+        }
+        if abilities.bladed_dive && bdcd == 0 {
+            bd = self.distance_cds_rec(distance - 10, secd, scd, ecd, bd_cd);
+        }
+        let se_ready;
+        if cooldowns.uses_charges() {
+            let surge_ready = abilities.surge && secd == 0 && scd <= cooldowns.surge_limit() as usize;
+            let escape_ready = abilities.escape && secd == 0 && ecd <= cooldowns.escape_limit() as usize;
+            if surge_ready {
+                surge = self.distance_cds_rec(distance - 10, lockout, scd + surge_cd, ecd, bdcd);
+            }
+            if escape_ready {
+                escape = self.distance_cds_rec(distance - 7, lockout, scd, ecd + escape_cd, bdcd);
+            }
+            se_ready = surge_ready || escape_ready;
+        } else {
+            if abilities.surge {
+                if secd == 0 {
+                    surge = self.distance_cds_rec(distance - 10, surge_cd, max(lockout, scd), escape_cd, bdcd);
+                } else if scd == 0 {
+                    surge = self.distance_cds_rec(distance - 10, max(lockout, secd), surge_cd, max(lockout, ecd), bdcd);
+                }
+            }
+            if abilities.escape {
+                if secd == 0 {
+                    escape = self.distance_cds_rec(distance - 7, escape_cd, surge_cd, max(lockout, ecd), bdcd);
+                } else if ecd == 0 {
+                    escape = self.distance_cds_rec(distance - 7, max(lockout, secd), max(lockout, scd), escape_cd, bdcd);
+                }
+            }
+            se_ready = (abilities.surge || abilities.escape) && secd == 0;
         }
-        if secd != 0 && bdcd != 0 {
+        let bd_ready = abilities.bladed_dive && bdcd == 0;
+        if !se_ready && !bd_ready {
             walk = self.distance_cds_rec(distance - 2, max(secd, 1) - 1, max(scd, 1) - 1, max(ecd, 1) - 1, max(bdcd, 1) - 1) + 1;
         }
-        let result = min(min(min(bd, surge), escape), walk);
+        let result = min(min(min(min(bd, surge), escape), walk), barge);
         self.data.insert((distance, secd, scd, ecd, bdcd), result);
         result
     }
 }
 
-pub fn setup(reset: bool) {
-    let progress_bar = ProgressBar::new(801);
-    fs::create_dir_all("MapData/BD").unwrap();
-    fs::create_dir_all("MapData/Move").unwrap();
-    fs::create_dir_all("MapData/SE").unwrap();
-    fs::create_dir_all("MapData/Walk").unwrap();
-    fs::create_dir_all("HeuristicData").unwrap();
-    if !Path::new("HeuristicData/l_infinity_cds.npy").try_exists().unwrap() || reset {
-        progress_bar.set_message("Generating heuristic data");
-        process_heuristic_data(500);
-    }
-    progress_bar.inc(1);
-    let mut moves = true;
-    'a: for i in 0..5 {
-        for j in 0..10 {
-            for k in 0..4 {
-                let path = format!("MapData/Move/move-{i}-{j}-{k}.npy");
-                if !Path::new(&path).try_exists().unwrap() {
-                    moves = false;
-                    break 'a;
-                }
-            }
-        }
-    }
-    if !moves || reset {
-        process_movement_data(&progress_bar);
-    } else {
-        progress_bar.inc(200);
+/// Generates the heuristic table for `loadout` unless it already exists, along with the table without
+/// Barge that `Heuristic` also needs when `loadout` has it.
+pub fn setup_heuristic(reset: bool, loadout: &Loadout, paths: &DataPaths) {
+    if loadout.abilities.barge {
+        setup_heuristic(reset, &loadout.without_barge(), paths);
     }
-    let mut walk = true;
-    'a: for i in 0..5 {
-        for j in 0..10 {
-            for k in 0..4 {
-                let path = format!("MapData/Walk/walk-{i}-{j}-{k}.npy");
-                if !Path::new(&path).try_exists().unwrap() {
-                    walk = false;
-                    break 'a;
-                }
-            }
-        }
+    fs::create_dir_all(&paths.heuristic_dir).unwrap();
+    if !paths.heuristic(loadout).try_exists().unwrap() || reset {
+        process_heuristic_data(500, loadout, paths);
     }
-    if !walk || reset {
-        process_walk_data(&progress_bar);
-    } else {
-        progress_bar.inc(200);
+}
+
+/// Generates the heuristic table if it is missing, and every map data chunk that is missing or out of date
+/// according to the manifest, using every available core. With `reset`, regenerates everything.
+pub fn setup(reset: bool, paths: &DataPaths) {
+    let workers = thread::available_parallelism().map_or(1, |n| n.get());
+    setup_with_workers(reset, paths, workers);
+}
+
+/// Same as `setup` with the chunks of each stage spread over `workers` threads. The output does not
+/// depend on `workers`.
+pub fn setup_with_workers(reset: bool, paths: &DataPaths, workers: usize) {
+    let chunk_count = CHUNKS_X * CHUNKS_Y * FLOORS;
+    let progress_bar = ProgressBar::new((1 + Stage::ALL.len() * chunk_count) as u64);
+    progress_bar.set_style(ProgressStyle::with_template("{msg} [{elapsed_precise}] {wide_bar} {pos}/{len} chunks ({per_sec}, {eta} left)").unwrap());
+    fs::create_dir_all(paths.map_dir.join("BD")).unwrap();
+    fs::create_dir_all(paths.map_dir.join("Move")).unwrap();
+    fs::create_dir_all(paths.map_dir.join("SE")).unwrap();
+    fs::create_dir_all(paths.map_dir.join("Walk")).unwrap();
+    progress_bar.set_message("Generating heuristic data");
+    setup_heuristic(reset, &Loadout::default(), paths);
+    progress_bar.inc(1);
+    let chunks: Vec<_> = (0..CHUNKS_X)
+        .flat_map(|i| (0..CHUNKS_Y).flat_map(move |j| (0..FLOORS).map(move |k| (i, j, k))))
+        .collect();
+    setup_chunks(reset, paths, workers, &chunks, &progress_bar);
+    progress_bar.finish();
+}
+
+/// Regenerates whichever of `chunks` are stale, or all of them with `reset`, stage by stage.
+fn setup_chunks(reset: bool, paths: &DataPaths, workers: usize, chunks: &[(usize, usize, usize)], progress_bar: &ProgressBar) {
+    let shared = SharedData::new(paths);
+    progress_bar.set_message("Hashing collision data");
+    let sources = SourceHashes::new(paths).unwrap();
+    let manifest = Mutex::new(Manifest::load(paths));
+    // Stages run in order, so a chunk whose collision data changed is decoded again before the walk and
+    // BD data of its neighbours, which read it, are rebuilt.
+    for stage in Stage::ALL {
+        let stale: Vec<_> = chunks.iter()
+            .copied()
+            .filter(|&(i, j, k)| reset || manifest.lock().unwrap().is_stale(stage, i, j, k, sources.inputs(stage, i, j, k), paths))
+            .collect();
+        // Keeps any outputs the check adopted, even if none of the stage's chunks turn out stale.
+        manifest.lock().unwrap().save().unwrap();
+        progress_bar.inc((chunks.len() - stale.len()) as u64);
+        progress_bar.set_message(match stage {
+            Stage::Move => "Decoding collision data",
+            Stage::Walk => "Generating walk data",
+            Stage::Bd => "Generating Bladed Dive data",
+            Stage::Se => "Generating Surge and Escape data",
+            Stage::Packed => "Packing chunks",
+        });
+        process_stage(progress_bar, stage, &stale, &shared, &sources, &manifest, workers);
     }
-    let mut bd = true;
-    'a: for i in 0..5 {
-        for j in 0..10 {
-            for k in 0..4 {
-                let path = format!("MapData/BD/bd-{i}-{j}-{k}.npy");
-                if !Path::new(&path).try_exists().unwrap() {
-                    bd = false;
-                    break 'a;
-                }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_map::{write_collision_data, TempDir};
+
+    /// An open patch straddling the border between chunks (1, 1) and (2, 1) of floor 0, with no way out of
+    /// it along its edges.
+    fn patch() -> Vec<(usize, usize, usize, u8)> {
+        let (x_range, y_range) = (2 * CHUNK_SIZE - 4..2 * CHUNK_SIZE + 4, CHUNK_SIZE + 20..CHUNK_SIZE + 26);
+        let mut tiles = Vec::new();
+        for x in x_range.clone() {
+            for y in y_range.clone() {
+                let inside = |direction: usize| {
+                    let (u, v) = adj_positions(x, y)[direction];
+                    x_range.contains(&u) && y_range.contains(&v)
+                };
+                let flags = (0..8).filter(|&direction| inside(direction)).map(|direction| [2, 32, 4, 64, 8, 128, 1, 16][direction]).sum();
+                tiles.push((x, y, 0, flags));
             }
         }
+        tiles
     }
-    if !bd || reset {
-        process_bd_data(&progress_bar);
-    } else {
-        progress_bar.inc(200);
-    }
-    let mut se = true;
-    'a: for i in 0..5 {
-        for j in 0..10 {
-            for k in 0..4 {
-                let path = format!("MapData/SE/se-{i}-{j}-{k}.npy");
-                if !Path::new(&path).try_exists().unwrap() {
-                    se = false;
-                    break 'a;
-                }
+
+    #[test]
+    fn parallel_setup_matches_serial_setup() {
+        let chunks = [(1, 1, 0), (2, 1, 0)];
+        let [serial, parallel] = [1, 4].map(|workers| {
+            let dir = TempDir::new();
+            let paths = DataPaths::new(dir.path());
+            write_collision_data(&paths, &patch());
+            for stage in ["Move", "Walk", "BD", "SE"] {
+                fs::create_dir_all(paths.map_dir.join(stage)).unwrap();
             }
+            setup_chunks(false, &paths, workers, &chunks, &ProgressBar::hidden());
+            let outputs: Vec<Vec<u8>> = Stage::ALL.iter()
+                .flat_map(|stage| chunks.map(|(i, j, k)| fs::read(stage.output(&paths, i, j, k)).unwrap()))
+                .collect();
+            (dir, outputs)
+        });
+        assert_eq!(serial.1, parallel.1);
+        // The patch has walk, Bladed Dive and Surge/Escape data on both sides of the border.
+        let paths = DataPaths::new(serial.0.path());
+        for (i, j, k) in chunks {
+            let x = if i == 1 { CHUNK_SIZE - 1 } else { 0 };
+            let walk: Array3<u64> = read_npy(paths.walk(i, j, k)).unwrap();
+            let bd: Array3<u64> = read_npy(paths.bd(i, j, k)).unwrap();
+            let se: Array3<u8> = read_npy(paths.se(i, j, k)).unwrap();
+            assert_ne!(walk[[x, 22, 0]], u64::MAX);
+            assert!(bd.slice(ndarray::s![x, 22, ..]).iter().any(|&word| word != 0));
+            assert!(se.slice(ndarray::s![x, 22, ..]).iter().any(|&offsets| offsets != 0));
         }
     }
-    if !se || reset {
-        process_se_data(&progress_bar);
-    } else {
-        progress_bar.inc(200);
+
+    #[test]
+    fn existing_outputs_are_adopted_and_corrupt_ones_regenerated() {
+        let chunks = [(1, 1, 0), (2, 1, 0)];
+        let dir = TempDir::new();
+        let paths = DataPaths::new(dir.path());
+        write_collision_data(&paths, &patch());
+        for stage in ["Move", "Walk", "BD", "SE"] {
+            fs::create_dir_all(paths.map_dir.join(stage)).unwrap();
+        }
+        setup_chunks(false, &paths, 1, &chunks, &ProgressBar::hidden());
+        let outputs: Vec<_> = Stage::ALL.iter()
+            .flat_map(|stage| chunks.map(|(i, j, k)| stage.output(&paths, i, j, k)))
+            .collect();
+        let modified = || outputs.iter().map(|output| fs::metadata(output).unwrap().modified().unwrap()).collect::<Vec<_>>();
+        let written = modified();
+
+        // Without a manifest, as in a MapData directory from before there was one.
+        fs::remove_file(paths.manifest()).unwrap();
+        setup_chunks(false, &paths, 1, &chunks, &ProgressBar::hidden());
+        assert_eq!(modified(), written);
+        assert_eq!(fs::read_to_string(paths.manifest()).unwrap().lines().count(), outputs.len());
+
+        // A flipped byte keeps the size, but not the contents the manifest recorded.
+        let walk = fs::read(&outputs[2]).unwrap();
+        let mut corrupt = walk.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        fs::write(&outputs[2], &corrupt).unwrap();
+        setup_chunks(false, &paths, 1, &chunks, &ProgressBar::hidden());
+        assert_eq!(fs::read(&outputs[2]).unwrap(), walk);
     }
-    progress_bar.finish();
 }
-
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use crate::{goal::Goal,
+            loadout::Loadout,
+            map_section::MapView,
+            pathfinding::{a_star_end_buffer, Heuristic, PathError, PathResult},
+            state::State,
+            transport::Player};
+
+/// One leg of a route, as inclusive indices into `Route::path`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Leg {
+    pub start: usize,
+    pub end: usize,
+    pub ticks: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub path: Vec<State>,
+    pub ticks: usize,
+    pub legs: Vec<Leg>,
+}
+
+/// Routes through `waypoints` in order. Each leg starts from the final state of the previous one, so
+/// cooldowns carry over between legs. Each leg is the fastest on its own, which is not always the fastest
+/// overall when a slower leg would have saved cooldowns for the next one.
+pub fn a_star_waypoints(start: State, waypoints: &[Goal], map: &impl MapView, heuristic: &Heuristic, loadout: &Loadout, player: &Player) -> Result<Route, PathError> {
+    let mut path = vec![start];
+    let mut legs = Vec::with_capacity(waypoints.len());
+    let mut ticks = 0;
+    for waypoint in waypoints {
+        let leg_start = path.len() - 1;
+        let result = a_star_end_buffer(path[leg_start].clone(), waypoint, map, heuristic, loadout, player)?;
+        path.extend(result.path.into_iter().skip(1));
+        ticks += result.ticks;
+        legs.push(Leg {
+            start: leg_start,
+            end: path.len() - 1,
+            ticks: result.ticks,
+        });
+    }
+    Ok(Route {
+        path,
+        ticks,
+        legs,
+    })
+}
+
+/// Tours over more goals than this are ordered heuristically instead of by exhaustive search.
+pub const EXACT_TOUR_LIMIT: usize = 7;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tour {
+    /// Indices into the solver's goals, in visiting order.
+    pub order: Vec<usize>,
+    pub route: Route,
+}
+
+/// Finds the fastest order to visit a set of goals. Leg searches are cached by their full start state
+/// and goal rather than by goal pair, because the tile and cooldowns an earlier leg ends with change how
+/// fast the next one is. Orders sharing a prefix and repeated 2-opt evaluations still only search each
+/// leg once.
+pub struct TourSolver<'a, M: MapView> {
+    goals: &'a [Goal],
+    map: &'a M,
+    heuristic: &'a Heuristic,
+    loadout: &'a Loadout,
+    player: &'a Player,
+    cache: HashMap<(State, usize), Result<PathResult, PathError>>,
+}
+
+impl<'a, M: MapView> TourSolver<'a, M> {
+    pub fn new(goals: &'a [Goal], map: &'a M, heuristic: &'a Heuristic, loadout: &'a Loadout, player: &'a Player) -> TourSolver<'a, M> {
+        TourSolver {
+            goals,
+            map,
+            heuristic,
+            loadout,
+            player,
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Tries every visiting order for up to `EXACT_TOUR_LIMIT` goals, and uses nearest neighbour improved
+    /// by 2-opt beyond that. Each leg is the fastest on its own, as in `a_star_waypoints`, so even the
+    /// exhaustive search is only exact among tours built from such legs.
+    pub fn solve(&mut self, start: State) -> Result<Tour, PathError> {
+        let order = if self.goals.len() <= EXACT_TOUR_LIMIT {
+            let mut best = None;
+            self.exact(&start, 0, &mut vec![false; self.goals.len()], &mut Vec::new(), &mut best)?;
+            best.ok_or(PathError::NoPath)?.1
+        } else {
+            let order = self.nearest_neighbour(&start)?;
+            self.two_opt(&start, order)?
+        };
+        self.build_tour(start, order)
+    }
+
+    fn leg(&mut self, from: &State, goal: usize) -> Result<(usize, State), PathError> {
+        let key = (from.clone(), goal);
+        if !self.cache.contains_key(&key) {
+            let result = a_star_end_buffer(from.clone(), &self.goals[goal], self.map, self.heuristic, self.loadout, self.player);
+            self.cache.insert(key.clone(), result);
+        }
+        match &self.cache[&key] {
+            Ok(result) => Ok((result.ticks, result.path.last().unwrap().clone())),
+            Err(err) => Err(err.clone()),
+        }
+    }
+
+    /// Like `leg`, but an unreachable goal is `None` rather than an error.
+    fn try_leg(&mut self, from: &State, goal: usize) -> Result<Option<(usize, State)>, PathError> {
+        match self.leg(from, goal) {
+            Ok(leg) => Ok(Some(leg)),
+            Err(PathError::NoPath) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn exact(&mut self, state: &State, ticks: usize, visited: &mut Vec<bool>, order: &mut Vec<usize>, best: &mut Option<(usize, Vec<usize>)>) -> Result<(), PathError> {
+        if order.len() == self.goals.len() {
+            if best.as_ref().is_none_or(|(best_ticks, _)| ticks < *best_ticks) {
+                *best = Some((ticks, order.clone()));
+            }
+            return Ok(());
+        }
+        for goal in 0..self.goals.len() {
+            if visited[goal] {
+                continue;
+            }
+            let Some((leg_ticks, end)) = self.try_leg(state, goal)? else {
+                continue;
+            };
+            let total = ticks + leg_ticks;
+            if best.as_ref().is_some_and(|(best_ticks, _)| total >= *best_ticks) {
+                continue;
+            }
+            visited[goal] = true;
+            order.push(goal);
+            self.exact(&end, total, visited, order, best)?;
+            order.pop();
+            visited[goal] = false;
+        }
+        Ok(())
+    }
+
+    fn nearest_neighbour(&mut self, start: &State) -> Result<Vec<usize>, PathError> {
+        let mut state = start.clone();
+        let mut remaining: Vec<usize> = (0..self.goals.len()).collect();
+        let mut order = Vec::with_capacity(remaining.len());
+        while !remaining.is_empty() {
+            let mut best: Option<(usize, usize, State)> = None;
+            for (i, &goal) in remaining.iter().enumerate() {
+                if let Some((ticks, end)) = self.try_leg(&state, goal)? {
+                    if best.as_ref().is_none_or(|(best_ticks, _, _)| ticks < *best_ticks) {
+                        best = Some((ticks, i, end));
+                    }
+                }
+            }
+            let (_, i, end) = best.ok_or(PathError::NoPath)?;
+            order.push(remaining.swap_remove(i));
+            state = end;
+        }
+        Ok(order)
+    }
+
+    fn tour_ticks(&mut self, start: &State, order: &[usize]) -> Result<Option<usize>, PathError> {
+        let mut state = start.clone();
+        let mut ticks = 0;
+        for &goal in order {
+            let Some((leg_ticks, end)) = self.try_leg(&state, goal)? else {
+                return Ok(None);
+            };
+            ticks += leg_ticks;
+            state = end;
+        }
+        Ok(Some(ticks))
+    }
+
+    /// Reverses segments of `order` while that makes the tour faster. Costs are recomputed for the whole
+    /// tour because reversing a segment changes the cooldowns every later leg starts with.
+    fn two_opt(&mut self, start: &State, mut order: Vec<usize>) -> Result<Vec<usize>, PathError> {
+        let mut best = self.tour_ticks(start, &order)?.ok_or(PathError::NoPath)?;
+        let mut improved = true;
+        while improved {
+            improved = false;
+            for i in 0..order.len() - 1 {
+                for j in i + 1..order.len() {
+                    order[i..=j].reverse();
+                    match self.tour_ticks(start, &order)? {
+                        Some(ticks) if ticks < best => {
+                            best = ticks;
+                            improved = true;
+                        }
+                        _ => order[i..=j].reverse(),
+                    }
+                }
+            }
+        }
+        Ok(order)
+    }
+
+    fn build_tour(&mut self, start: State, order: Vec<usize>) -> Result<Tour, PathError> {
+        let mut path = vec![start];
+        let mut legs = Vec::with_capacity(order.len());
+        let mut ticks = 0;
+        for &goal in &order {
+            let leg_start = path.len() - 1;
+            let from = path[leg_start].clone();
+            self.leg(&from, goal)?;
+            let result = self.cache[&(from, goal)].as_ref().unwrap();
+            path.extend(result.path.iter().skip(1).cloned());
+            ticks += result.ticks;
+            legs.push(Leg {
+                start: leg_start,
+                end: path.len() - 1,
+                ticks: result.ticks,
+            });
+        }
+        Ok(Tour {
+            order,
+            route: Route {
+                path,
+                ticks,
+                legs,
+            },
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{loadout::{AbilitySet, CooldownProfile},
+                test_map::GridMap};
+
+    fn solve(goals: &[Goal]) -> Tour {
+        let map = GridMap::new(40, 5, 1, &[]);
+        let loadout = Loadout::new(AbilitySet::NONE, CooldownProfile::new(3, 3, 3, 6, 1));
+        let heuristic = Heuristic::generate(40, &loadout);
+        let start = State { pos_x: 2, pos_y: 2, floor: 0, direction: 0, secd: 0, scd: 0, ecd: 0, bdcd: 0, bgcd: 0 };
+        TourSolver::new(goals, &map, &heuristic, &loadout, &Player::default()).solve(start).unwrap()
+    }
+
+    #[test]
+    fn exact_tour_visits_goals_along_the_line() {
+        let goals = [Goal::Tile { x: 17, y: 2 }, Goal::Tile { x: 7, y: 2 }, Goal::Tile { x: 12, y: 2 }];
+        let tour = solve(&goals);
+        assert_eq!(tour.order, [1, 2, 0]);
+        // Five tiles per leg at two a tick.
+        assert_eq!(tour.route.ticks, 9);
+        assert_eq!(tour.route.legs.iter().map(|leg| leg.ticks).collect::<Vec<_>>(), [3, 3, 3]);
+    }
+
+    #[test]
+    fn large_tour_falls_back_to_an_ordered_heuristic() {
+        let xs = [30, 6, 22, 10, 34, 14, 26, 18];
+        let goals: Vec<Goal> = xs.iter().map(|&x| Goal::Tile { x, y: 2 }).collect();
+        let tour = solve(&goals);
+        let visited: Vec<u16> = tour.order.iter().map(|&i| xs[i]).collect();
+        assert_eq!(visited, [6, 10, 14, 18, 22, 26, 30, 34]);
+        assert_eq!(tour.route.ticks, 16);
+    }
+
+    #[test]
+    fn waypoint_legs_join_up_and_carry_cooldowns_over() {
+        let map = GridMap::new(40, 5, 1, &[]);
+        let loadout = Loadout::new(AbilitySet::default(), CooldownProfile::new(3, 8, 8, 8, 1));
+        let heuristic = Heuristic::generate(40, &loadout);
+        let player = Player::default();
+        let start = State { pos_x: 2, pos_y: 2, floor: 0, direction: 2, secd: 0, scd: 0, ecd: 0, bdcd: 0, bgcd: 0 };
+        let waypoints = [Goal::Tile { x: 30, y: 2 }, Goal::Tile { x: 20, y: 2 }, Goal::Tile { x: 25, y: 4 }];
+        let route = a_star_waypoints(start.clone(), &waypoints, &map, &heuristic, &loadout, &player).unwrap();
+
+        assert_eq!(route.path[0], start);
+        assert_eq!(route.legs.len(), waypoints.len());
+        assert_eq!(route.legs[0].start, 0);
+        assert_eq!(route.legs.last().unwrap().end, route.path.len() - 1);
+        for (leg, next) in route.legs.iter().zip(&route.legs[1..]) {
+            assert_eq!(leg.end, next.start);
+        }
+        for (leg, waypoint) in route.legs.iter().zip(&waypoints) {
+            assert!(route.path[leg.end].at_goal(waypoint), "{leg:?} ends off {waypoint:?}");
+        }
+        assert_eq!(route.ticks, route.legs.iter().map(|leg| leg.ticks).sum::<usize>());
+
+        // The first leg uses abilities whose cooldowns are still running when the second starts, and the
+        // second is searched from exactly that state.
+        let handover = &route.path[route.legs[1].start];
+        assert!([handover.scd, handover.ecd, handover.bdcd].iter().any(|&cd| cd > 0), "{handover:?}");
+        let second = a_star_end_buffer(handover.clone(), &waypoints[1], &map, &heuristic, &loadout, &player).unwrap();
+        assert_eq!(route.legs[1].ticks, second.ticks);
+        assert_eq!(route.path[route.legs[1].start..=route.legs[1].end], second.path[..]);
+    }
+}
@@ -0,0 +1,370 @@
+use radix_heap::RadixHeapMap;
+use std::{cmp::Reverse,
+          collections::HashMap,
+          hash::{BuildHasherDefault, Hasher}};
+use crate::{loadout::Loadout,
+            state::State};
+
+const X_BITS: u32 = 13;
+const Y_BITS: u32 = 14;
+const FLOOR_BITS: u32 = 2;
+const DIRECTION_BITS: u32 = 3;
+const POSITION_BITS: u32 = X_BITS + Y_BITS + FLOOR_BITS + DIRECTION_BITS;
+
+/// Layout of a `State` packed into a `u64`: position, floor and direction in the low 32 bits, then each
+/// cooldown in as few bits as the loadout's longest cooldown for it needs.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Packing {
+    widths: [u32; 5],
+}
+
+impl Packing {
+    /// `None` if the loadout's cooldowns are too long to fit beside the position.
+    pub(crate) fn new(loadout: &Loadout) -> Option<Packing> {
+        let [secd, scd, ecd, bdcd] = loadout.cooldowns.max_cooldowns();
+        let widths = [secd, scd, ecd, bdcd, loadout.cooldowns.barge()].map(|max| u8::BITS - max.leading_zeros());
+        (POSITION_BITS + widths.iter().sum::<u32>() <= u64::BITS).then_some(Packing { widths })
+    }
+
+    /// Whether `state` can be packed without losing information.
+    pub(crate) fn fits(&self, state: &State) -> bool {
+        let cooldowns = [state.secd, state.scd, state.ecd, state.bdcd, state.bgcd];
+        (state.pos_x as u64) < 1 << X_BITS
+            && (state.pos_y as u64) < 1 << Y_BITS
+            && (state.floor as u64) < 1 << FLOOR_BITS
+            && (state.direction as u64) < 1 << DIRECTION_BITS
+            && cooldowns.iter().zip(self.widths).all(|(&cd, width)| (cd as u64) < 1 << width)
+    }
+
+    fn pack(&self, state: &State) -> u64 {
+        let mut packed = state.pos_x as u64
+            | (state.pos_y as u64) << X_BITS
+            | (state.floor as u64) << (X_BITS + Y_BITS)
+            | (state.direction as u64) << (X_BITS + Y_BITS + FLOOR_BITS);
+        let mut shift = POSITION_BITS;
+        for (cd, width) in [state.secd, state.scd, state.ecd, state.bdcd, state.bgcd].into_iter().zip(self.widths) {
+            packed |= (cd as u64) << shift;
+            shift += width;
+        }
+        packed
+    }
+
+    fn unpack(&self, packed: u64) -> State {
+        let field = |shift: u32, width: u32| (packed >> shift) & ((1 << width) - 1);
+        let mut cooldowns = [0; 5];
+        let mut shift = POSITION_BITS;
+        for (cd, width) in cooldowns.iter_mut().zip(self.widths) {
+            *cd = field(shift, width) as u8;
+            shift += width;
+        }
+        State {
+            pos_x: field(0, X_BITS) as u16,
+            pos_y: field(X_BITS, Y_BITS) as u16,
+            floor: field(X_BITS + Y_BITS, FLOOR_BITS) as u8,
+            direction: field(X_BITS + Y_BITS + FLOOR_BITS, DIRECTION_BITS) as u8,
+            secd: cooldowns[0],
+            scd: cooldowns[1],
+            ecd: cooldowns[2],
+            bdcd: cooldowns[3],
+            bgcd: cooldowns[4],
+        }
+    }
+}
+
+const NO_NODE: u32 = u32::MAX;
+
+/// How `astar` stores states in its nodes and finds the node for a state.
+pub(crate) trait NodeIndex {
+    type Key: PartialEq;
+
+    fn key(&self, state: &State) -> Self::Key;
+
+    fn state(&self, key: &Self::Key) -> State;
+
+    /// The node for `key`, `NO_NODE` until one is inserted.
+    fn slot(&mut self, key: &Self::Key) -> &mut u32;
+}
+
+/// Multiply-rotate hash in the style of FxHash, far cheaper than SipHash for a single `u64`. A multiply
+/// only carries bits upwards, so the result is rotated to bring the well mixed high bits down to where
+/// the hash table picks its bucket.
+#[derive(Default)]
+pub(crate) struct PackedHasher {
+    hash: u64,
+}
+
+impl Hasher for PackedHasher {
+    fn finish(&self) -> u64 {
+        self.hash.rotate_left(26)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_u64(byte as u64);
+        }
+    }
+
+    fn write_u64(&mut self, word: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+    }
+}
+
+/// Packs states into a `u64` and maps each packed state to its node.
+pub(crate) struct PackedIndex {
+    packing: Packing,
+    slots: HashMap<u64, u32, BuildHasherDefault<PackedHasher>>,
+}
+
+impl PackedIndex {
+    pub(crate) fn new(packing: Packing) -> PackedIndex {
+        PackedIndex { packing, slots: HashMap::default() }
+    }
+}
+
+impl NodeIndex for PackedIndex {
+    type Key = u64;
+
+    fn key(&self, state: &State) -> u64 {
+        debug_assert!(self.packing.fits(state));
+        self.packing.pack(state)
+    }
+
+    fn state(&self, key: &u64) -> State {
+        self.packing.unpack(*key)
+    }
+
+    fn slot(&mut self, packed: &u64) -> &mut u32 {
+        self.slots.entry(*packed).or_insert(NO_NODE)
+    }
+}
+
+/// Keeps whole states, for loadouts whose cooldowns are too long to pack.
+#[derive(Default)]
+pub(crate) struct HashedIndex {
+    slots: HashMap<State, u32>,
+}
+
+impl NodeIndex for HashedIndex {
+    type Key = State;
+
+    fn key(&self, state: &State) -> State {
+        state.clone()
+    }
+
+    fn state(&self, key: &State) -> State {
+        key.clone()
+    }
+
+    fn slot(&mut self, key: &State) -> &mut u32 {
+        self.slots.entry(key.clone()).or_insert(NO_NODE)
+    }
+}
+
+struct Node<K> {
+    state: K,
+    parent: u32,
+    cost: u32,
+}
+
+/// A* with a radix heap as the open list, returning the same as `pathfinding::prelude::astar`.
+/// `successors` fills the buffer it is given, which is reused for every expansion.
+///
+/// The radix heap needs keys that never drop below the last one popped, so estimates are raised to at
+/// least the current node's. That only ever delays expanding a node, so with an admissible heuristic the
+/// path found is still optimal.
+pub(crate) fn astar<I, N, H, S>(start: &State, mut index: I, mut successors: N, mut heuristic: H, mut success: S) -> Option<(Vec<State>, usize)>
+where
+    I: NodeIndex,
+    N: FnMut(&State, &mut Vec<(State, usize)>),
+    H: FnMut(&State) -> usize,
+    S: FnMut(&State) -> bool,
+{
+    let key = index.key(start);
+    *index.slot(&key) = 0;
+    let mut nodes = vec![Node { state: key, parent: NO_NODE, cost: 0 }];
+    let mut open = RadixHeapMap::new();
+    let mut adjacent = Vec::new();
+    open.push(Reverse(heuristic(start) as u32), (0u32, 0u32));
+    while let Some((Reverse(estimate), (i, cost))) = open.pop() {
+        if cost > nodes[i as usize].cost {
+            continue;
+        }
+        let state = index.state(&nodes[i as usize].state);
+        if success(&state) {
+            return Some((reconstruct(&nodes, &index, i), cost as usize));
+        }
+        successors(&state, &mut adjacent);
+        for (next, move_cost) in &adjacent {
+            let next_cost = cost + *move_cost as u32;
+            let key = index.key(next);
+            let slot = index.slot(&key);
+            let j = if *slot == NO_NODE {
+                *slot = nodes.len() as u32;
+                nodes.push(Node { state: key, parent: i, cost: next_cost });
+                *slot
+            } else {
+                let j = *slot;
+                let node = &mut nodes[j as usize];
+                if node.cost <= next_cost {
+                    continue;
+                }
+                node.parent = i;
+                node.cost = next_cost;
+                j
+            };
+            let next_estimate = (next_cost + heuristic(next) as u32).max(estimate);
+            open.push(Reverse(next_estimate), (j, next_cost));
+        }
+    }
+    None
+}
+
+fn reconstruct<I: NodeIndex>(nodes: &[Node<I::Key>], index: &I, mut i: u32) -> Vec<State> {
+    let mut path = Vec::new();
+    while i != NO_NODE {
+        path.push(index.state(&nodes[i as usize].state));
+        i = nodes[i as usize].parent;
+    }
+    path.reverse();
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+    use crate::{goal::Goal,
+                loadout::{AbilitySet, CooldownProfile},
+                pathfinding::{a_star_hashed, a_star_multi_goal, a_star_reference, successors, Heuristic, PathResult},
+                test_map::GridMap,
+                transport::Player};
+
+    fn round_trip(loadout: &Loadout) {
+        let packing = Packing::new(loadout).unwrap();
+        let [secd, scd, ecd, bdcd] = loadout.cooldowns.max_cooldowns();
+        let largest = State {
+            pos_x: (1 << X_BITS) - 1,
+            pos_y: (1 << Y_BITS) - 1,
+            floor: (1 << FLOOR_BITS) - 1,
+            direction: (1 << DIRECTION_BITS) - 1,
+            secd,
+            scd,
+            ecd,
+            bdcd,
+            bgcd: loadout.cooldowns.barge(),
+        };
+        // Every field at the top of its width, so a field spilling into the next would show.
+        let widest = State {
+            secd: ((1u16 << packing.widths[0]) - 1) as u8,
+            scd: ((1u16 << packing.widths[1]) - 1) as u8,
+            ecd: ((1u16 << packing.widths[2]) - 1) as u8,
+            bdcd: ((1u16 << packing.widths[3]) - 1) as u8,
+            bgcd: ((1u16 << packing.widths[4]) - 1) as u8,
+            ..largest.clone()
+        };
+        let smallest = State { pos_x: 0, pos_y: 0, floor: 0, direction: 0, secd: 0, scd: 0, ecd: 0, bdcd: 0, bgcd: 0 };
+        for state in [largest, widest, smallest] {
+            assert!(packing.fits(&state), "{state:?}");
+            assert_eq!(packing.unpack(packing.pack(&state)), state);
+        }
+    }
+
+    #[test]
+    fn packing_round_trips_every_field_at_its_widest() {
+        round_trip(&Loadout::default());
+        round_trip(&Loadout::new(AbilitySet::ALL, CooldownProfile::new(85, 17, 17, 34, 2).with_charges(3, 2).unwrap()));
+        round_trip(&Loadout::new(AbilitySet::NONE, CooldownProfile::new(1, 1, 1, 1, 1)));
+    }
+
+    #[test]
+    fn packing_rejects_what_it_cannot_hold() {
+        let packing = Packing::new(&Loadout::default()).unwrap();
+        let state = State { pos_x: 100, pos_y: 100, floor: 0, direction: 0, secd: 0, scd: 0, ecd: 0, bdcd: 0, bgcd: 0 };
+        assert!(!packing.fits(&State { pos_x: 1 << X_BITS, ..state.clone() }));
+        assert!(!packing.fits(&State { floor: 1 << FLOOR_BITS, ..state.clone() }));
+        assert!(!packing.fits(&State { bdcd: 1 << packing.widths[3], ..state }));
+        assert!(Packing::new(&Loadout::new(AbilitySet::ALL, CooldownProfile::new(255, 255, 255, 255, 255))).is_none());
+    }
+
+    fn state_at(x: u16, y: u16, direction: u8) -> State {
+        State { pos_x: x, pos_y: y, floor: 0, direction, secd: 0, scd: 0, ecd: 0, bdcd: 0, bgcd: 0 }
+    }
+
+    /// Checks that each step of `result` is a move `successors` offers, and that their costs add up.
+    fn assert_valid(result: &PathResult, map: &GridMap, loadout: &Loadout, goals: &[Goal]) {
+        let mut ticks = 0;
+        let mut adjacent = Vec::new();
+        for step in result.path.windows(2) {
+            successors(&step[0], map, loadout, &Player::default(), goals, &mut adjacent);
+            let cost = adjacent.iter().filter(|(next, _)| *next == step[1]).map(|&(_, cost)| cost).min();
+            ticks += cost.unwrap_or_else(|| panic!("no move from {:?} to {:?}", step[0], step[1]));
+        }
+        assert_eq!(ticks, result.ticks);
+        assert!(result.path.last().unwrap().at_goal(&goals[result.goal]));
+    }
+
+    #[test]
+    fn packed_search_matches_the_hashed_and_reference_searches() {
+        let walled: Vec<_> = (2..12).map(|y| (6, y, 0)).chain((0..9).map(|x| (x, 9, 0))).collect();
+        let maps = [GridMap::new(14, 14, 2, &walled), GridMap::new(14, 14, 2, &[]), GridMap::new(12, 6, 2, &[(5, 0, 0), (5, 1, 0), (5, 2, 0)])];
+        let loadouts = [
+            Loadout::new(AbilitySet::default(), CooldownProfile::new(3, 4, 5, 6, 1)),
+            Loadout::new(AbilitySet::ALL, CooldownProfile::new(3, 4, 5, 6, 4)),
+            Loadout::new(AbilitySet::default(), CooldownProfile::new(3, 4, 5, 6, 1).with_charges(2, 2).unwrap()),
+            Loadout::new(AbilitySet::NONE, CooldownProfile::default()),
+        ];
+        let routes = [
+            ((1, 1), vec![Goal::Tile { x: 11, y: 5 }]),
+            ((2, 12), vec![Goal::Tile { x: 11, y: 3 }, Goal::rect(13, 13, 12, 12)]),
+            ((11, 5), vec![Goal::near(0, 0)]),
+            // Only on a floor nothing leads to, so the whole of the first floor is searched.
+            ((3, 3), vec![Goal::Tile { x: 4, y: 4 }.on_floor(1)]),
+        ];
+        for (i, map) in maps.iter().enumerate() {
+            for loadout in &loadouts {
+                let heuristic = Heuristic::generate(30, loadout);
+                for (direction, ((x, y), goals)) in routes.iter().enumerate() {
+                    // Exhausting the larger maps takes too long to do for every loadout.
+                    if i < 2 && goals[0].floor() == Some(1) {
+                        continue;
+                    }
+                    let start = state_at(*x, *y, direction as u8 * 2);
+                    let packed = a_star_multi_goal(start.clone(), goals, map, &heuristic, loadout, &Player::default());
+                    let hashed = a_star_hashed(start.clone(), goals, map, &heuristic, loadout, &Player::default());
+                    let reference = a_star_reference(start.clone(), goals, map, &heuristic, loadout, &Player::default());
+                    // The index does not change the order states are expanded in, so the results are
+                    // identical. The reference search breaks ties its own way, so only its cost has to match.
+                    assert_eq!(packed, hashed, "{loadout:?} from {start:?} to {goals:?}");
+                    match (&packed, &reference) {
+                        (Ok(packed), Ok(reference)) => {
+                            assert_eq!(packed.ticks, reference.ticks, "{loadout:?} from {start:?} to {goals:?}");
+                            assert_eq!(packed.path.first(), Some(&start));
+                            assert_valid(packed, map, loadout, goals);
+                        }
+                        _ => assert_eq!(packed, reference, "{loadout:?} from {start:?} to {goals:?}"),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Searches every state reachable from `start`, none of which is a goal.
+    fn exhaust(index: impl NodeIndex, map: &GridMap, loadout: &Loadout, start: &State) -> Duration {
+        let goals = [Goal::Tile { x: 0, y: 0 }.on_floor(1)];
+        let next = |s: &State, adjacent: &mut Vec<(State, usize)>| successors(s, map, loadout, &Player::default(), &goals, adjacent);
+        let started = Instant::now();
+        assert!(astar(start, index, next, |_| 0, |_| false).is_none());
+        started.elapsed()
+    }
+
+    #[test]
+    fn packed_index_keeps_up_with_hashing_whole_states_on_exhaustive_searches() {
+        let map = GridMap::new(16, 16, 1, &[]);
+        let loadout = Loadout::new(AbilitySet::default(), CooldownProfile::new(3, 4, 5, 6, 1));
+        let start = state_at(8, 8, 0);
+        let packed = exhaust(PackedIndex::new(Packing::new(&loadout).unwrap()), &map, &loadout, &start);
+        let hashed = exhaust(HashedIndex::default(), &map, &loadout, &start);
+        // Lookups take constant time, so packing can only help. Allow for noise.
+        assert!(packed < hashed * 2, "packed {packed:?}, hashed {hashed:?}");
+    }
+}
@@ -1,34 +1,46 @@
 use std::cmp::max;
-use crate::map_section::MapSection;
+use crate::{goal::Goal,
+            loadout::CooldownProfile,
+            map_section::MapView,
+            transport::{Teleport, Transport}};
+
+/// Furthest Chebyshev distance from which Barge can reach its target.
+pub const BARGE_RANGE: u16 = 10;
 
 #[derive(PartialEq, Eq, Hash, Clone, Debug)]
 pub struct State {
     pub pos_x: u16,
     pub pos_y: u16,
+    pub floor: u8,
     pub direction: u8,
     pub secd: u8,
     pub scd: u8,
     pub ecd: u8,
     pub bdcd: u8,
+    pub bgcd: u8,
 }
 
 impl State {
     pub fn update(&self) -> State {
         let pos_x = self.pos_x;
         let pos_y = self.pos_y;
+        let floor = self.floor;
         let direction = self.direction;
         let secd = max(self.secd, 1) - 1;
         let scd = max(self.scd, 1) - 1;
         let ecd= max(self.ecd, 1) - 1;
         let bdcd = max(self.bdcd, 1) - 1;
+        let bgcd = max(self.bgcd, 1) - 1;
         State {
             pos_x,
             pos_y,
+            floor,
             direction,
             secd,
             scd,
             ecd,
             bdcd,
+            bgcd,
         }
     }
 
@@ -36,94 +48,253 @@ impl State {
         State {
             pos_x: x,
             pos_y: y,
+            floor: self.floor,
             direction,
             secd: self.secd,
             scd: self.scd,
             ecd: self.ecd,
             bdcd: self.bdcd,
+            bgcd: self.bgcd,
+        }
+    }
+
+    /// Ticks every cooldown down by `ticks` at once.
+    pub fn update_by(&self, ticks: usize) -> State {
+        let ticks = ticks.min(u8::MAX as usize) as u8;
+        State {
+            secd: self.secd.saturating_sub(ticks),
+            scd: self.scd.saturating_sub(ticks),
+            ecd: self.ecd.saturating_sub(ticks),
+            bdcd: self.bdcd.saturating_sub(ticks),
+            bgcd: self.bgcd.saturating_sub(ticks),
+            ..self.clone()
+        }
+    }
+
+    pub fn transport(&self, transport: &Transport) -> State {
+        State {
+            pos_x: transport.to_x,
+            pos_y: transport.to_y,
+            floor: transport.to_floor,
+            ..self.update_by(transport.ticks)
+        }
+    }
+
+    pub fn teleport(&self, teleport: &Teleport) -> State {
+        State {
+            pos_x: teleport.to_x,
+            pos_y: teleport.to_y,
+            floor: teleport.to_floor,
+            ..self.update_by(teleport.ticks)
         }
     }
 
-    pub fn surge(&self, section: &MapSection) -> State{
-        let (new_x, new_y) = section.surge_range(self.pos_x, self.pos_y, self.direction);
-        if self.secd == 0 {
+    pub fn surge(&self, section: &impl MapView, cooldowns: &CooldownProfile) -> State{
+        let (new_x, new_y) = section.surge_range(self.pos_x, self.pos_y, self.floor, self.direction);
+        if cooldowns.uses_charges() {
+            assert!(self.can_surge(cooldowns));
+            State {
+                pos_x: new_x as u16,
+                pos_y: new_y as u16,
+                floor: self.floor,
+                direction: self.direction,
+                secd: cooldowns.lockout(),
+                scd: self.scd + cooldowns.surge(),
+                ecd: self.ecd,
+                bdcd: self.bdcd,
+                bgcd: self.bgcd,
+            }
+        } else if self.secd == 0 {
             State {
                 pos_x: new_x as u16,
                 pos_y: new_y as u16,
+                floor: self.floor,
                 direction: self.direction,
-                secd: 17,
-                scd: max(2, self.scd),
-                ecd: 17,
+                secd: cooldowns.surge(),
+                scd: max(cooldowns.lockout(), self.scd),
+                ecd: cooldowns.escape(),
                 bdcd: self.bdcd,
+                bgcd: self.bgcd,
             }
         } else if self.scd == 0 {
             State {
                 pos_x: new_x as u16,
                 pos_y: new_y as u16,
+                floor: self.floor,
                 direction: self.direction,
-                secd: max(2, self.secd),
-                scd: 17,
-                ecd: max(2, self.ecd),
+                secd: max(cooldowns.lockout(), self.secd),
+                scd: cooldowns.surge(),
+                ecd: max(cooldowns.lockout(), self.ecd),
                 bdcd: self.bdcd,
+                bgcd: self.bgcd,
             }
         } else {
             panic!()
         }
     }
 
-    pub fn escape(&self, section: &MapSection) -> State {
-        let (new_x, new_y) = section.escape_range(self.pos_x, self.pos_y, self.direction);
-        if self.secd == 0 {
+    pub fn escape(&self, section: &impl MapView, cooldowns: &CooldownProfile) -> State {
+        let (new_x, new_y) = section.escape_range(self.pos_x, self.pos_y, self.floor, self.direction);
+        if cooldowns.uses_charges() {
+            assert!(self.can_escape(cooldowns));
+            State {
+                pos_x: new_x as u16,
+                pos_y: new_y as u16,
+                floor: self.floor,
+                direction: self.direction,
+                secd: cooldowns.lockout(),
+                scd: self.scd,
+                ecd: self.ecd + cooldowns.escape(),
+                bdcd: self.bdcd,
+                bgcd: self.bgcd,
+            }
+        } else if self.secd == 0 {
             State {
                 pos_x: new_x as u16,
                 pos_y: new_y as u16,
+                floor: self.floor,
                 direction: self.direction,
-                secd: 17,
-                scd: 17,
-                ecd: max(2, self.ecd),
+                secd: cooldowns.escape(),
+                scd: cooldowns.surge(),
+                ecd: max(cooldowns.lockout(), self.ecd),
                 bdcd: self.bdcd,
+                bgcd: self.bgcd,
             }
         } else if self.ecd == 0 {
             State {
                 pos_x: new_x as u16,
                 pos_y: new_y as u16,
+                floor: self.floor,
                 direction: self.direction,
-                secd: max(2, self.secd),
-                scd: max(2, self.scd),
-                ecd: 17,
+                secd: max(cooldowns.lockout(), self.secd),
+                scd: max(cooldowns.lockout(), self.scd),
+                ecd: cooldowns.escape(),
                 bdcd: self.bdcd,
+                bgcd: self.bgcd,
             }
         } else {
             panic!()
         }
     }
 
-    pub fn bd(&self, x:u16, y: u16, direction: u8) -> State{
+    pub fn bd(&self, x:u16, y: u16, direction: u8, cooldowns: &CooldownProfile) -> State{
         assert_eq!(self.bdcd, 0);
         State {
             pos_x: x,
             pos_y: y,
+            floor: self.floor,
             direction,
             secd: self.secd,
             scd: self.scd,
             ecd: self.ecd,
-            bdcd: 17,
+            bdcd: cooldowns.bladed_dive(),
+            bgcd: self.bgcd,
         }
     }
 
+    pub fn barge(&self, x: u16, y: u16, direction: u8, cooldowns: &CooldownProfile) -> State {
+        assert_eq!(self.bgcd, 0);
+        State {
+            pos_x: x,
+            pos_y: y,
+            floor: self.floor,
+            direction,
+            secd: self.secd,
+            scd: self.scd,
+            ecd: self.ecd,
+            bdcd: self.bdcd,
+            bgcd: cooldowns.barge(),
+        }
+    }
+
+    pub fn can_barge(&self) -> bool {
+        self.bgcd == 0
+    }
+
     pub fn can_bd(&self) -> bool{
         self.bdcd == 0
     }
 
-    pub fn can_surge(&self) -> bool {
-        self.secd == 0 || self.scd == 0
+    pub fn can_surge(&self, cooldowns: &CooldownProfile) -> bool {
+        if cooldowns.uses_charges() {
+            self.secd == 0 && self.scd <= cooldowns.surge_limit()
+        } else {
+            self.secd == 0 || self.scd == 0
+        }
+    }
+
+    pub fn can_escape(&self, cooldowns: &CooldownProfile) -> bool {
+        if cooldowns.uses_charges() {
+            self.secd == 0 && self.ecd <= cooldowns.escape_limit()
+        } else {
+            self.secd == 0 || self.ecd == 0
+        }
+    }
+
+    /// Surge charges currently available. Only meaningful when `cooldowns` uses charges.
+    pub fn surge_charges(&self, cooldowns: &CooldownProfile) -> u8 {
+        cooldowns.surge_charges().saturating_sub(self.scd.div_ceil(cooldowns.surge().max(1)))
+    }
+
+    /// Escape charges currently available. Only meaningful when `cooldowns` uses charges.
+    pub fn escape_charges(&self, cooldowns: &CooldownProfile) -> u8 {
+        cooldowns.escape_charges().saturating_sub(self.ecd.div_ceil(cooldowns.escape().max(1)))
     }
 
-    pub fn can_escape(&self) -> bool {
-        self.secd == 0 || self.ecd == 0
+    pub fn at_goal(&self, goal: &Goal) -> bool{
+        goal.floor().is_none_or(|floor| floor == self.floor) && goal.contains(self.pos_x, self.pos_y)
     }
 
-    pub fn at_goal(&self, end: &(u16, u16)) -> bool{
-        end.0 - 1 <= self.pos_x && self.pos_x <= end.0 + 1  && end.1 - 1 <= self.pos_y && self.pos_y <= end.1 + 1
+    pub fn at_any_goal(&self, goals: &[Goal]) -> bool {
+        goals.iter().any(|goal| self.at_goal(goal))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_map::GridMap;
+
+    fn fresh(x: u16, y: u16, direction: u8) -> State {
+        State { pos_x: x, pos_y: y, floor: 0, direction, secd: 0, scd: 0, ecd: 0, bdcd: 0, bgcd: 0 }
+    }
+
+    #[test]
+    fn legacy_surge_and_escape_put_each_ability_on_its_own_cooldown() {
+        let map = GridMap::new(40, 40, 1, &[]);
+        let cooldowns = CooldownProfile::new(20, 10, 17, 34, 2);
+
+        let surged = fresh(15, 15, 2).surge(&map, &cooldowns);
+        assert_eq!((surged.pos_x, surged.pos_y), (25, 15));
+        assert_eq!((surged.secd, surged.scd, surged.ecd), (20, cooldowns.lockout(), 10));
+
+        let escaped = fresh(15, 15, 2).escape(&map, &cooldowns);
+        assert_eq!((escaped.pos_x, escaped.pos_y), (8, 15));
+        assert_eq!((escaped.secd, escaped.scd, escaped.ecd), (10, 20, cooldowns.lockout()));
+    }
+
+    #[test]
+    fn legacy_escape_after_surge_waits_for_its_own_cooldown() {
+        let map = GridMap::new(40, 40, 1, &[]);
+        let cooldowns = CooldownProfile::new(20, 10, 17, 34, 2);
+
+        let surged = fresh(15, 15, 2).surge(&map, &cooldowns);
+        assert!(!surged.update_by(9).can_escape(&cooldowns));
+        assert!(surged.update_by(10).can_escape(&cooldowns));
+        assert!(surged.update_by(cooldowns.lockout() as usize).can_surge(&cooldowns));
+    }
+
+    #[test]
+    fn charges_stack_the_recharge_timer_up_to_the_last_charge() {
+        let map = GridMap::new(80, 20, 1, &[]);
+        let cooldowns = CooldownProfile::new(85, 17, 17, 34, 2).with_charges(3, 1).unwrap();
+        let mut state = fresh(5, 10, 2);
+        for charges_left in (0..3).rev() {
+            assert!(state.can_surge(&cooldowns));
+            state = state.surge(&map, &cooldowns).update_by(cooldowns.lockout() as usize);
+            assert_eq!(state.surge_charges(&cooldowns), charges_left);
+        }
+        assert_eq!(state.scd, 255 - 3 * cooldowns.lockout());
+        assert!(!state.can_surge(&cooldowns));
+    }
+}
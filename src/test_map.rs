@@ -0,0 +1,199 @@
+//! Small synthetic maps for unit tests, so they do not depend on the generated map data.
+
+use flate2::{write::ZlibEncoder, Compression};
+use std::{collections::{HashMap, HashSet},
+          env,
+          fs,
+          io::Write,
+          path::{Path, PathBuf},
+          process,
+          sync::atomic::{AtomicUsize, Ordering}};
+use crate::{data_paths::DataPaths,
+            map_section::MapView,
+            transport::{Teleport, Transport},
+            util::{CHUNK_SIZE, FLOORS, RS_HEIGHT, RS_LENGTH}};
+
+/// Tiles reachable from each open tile, with the direction faced on arrival.
+type Ranges = HashMap<(u16, u16, u8), Vec<(u16, u16, u8)>>;
+
+const OFFSETS: [(i32, i32); 8] = [(0, 1), (1, 1), (1, 0), (1, -1), (0, -1), (-1, -1), (-1, 0), (-1, 1)];
+
+/// Direction index used by the movement data for a step by `(dx, dy)`.
+fn direction(dx: i32, dy: i32) -> u8 {
+    OFFSETS.iter().position(|&offset| offset == (dx.signum(), dy.signum())).unwrap() as u8
+}
+
+/// An open rectangle of `width` by `height` tiles on each of `floors` floors, with some tiles walled off.
+/// Walking covers up to two tiles a tick, Bladed Dive reaches any open tile within 10, and Surge and
+/// Escape travel 10 and 7 tiles until the next wall.
+pub(crate) struct GridMap {
+    width: u16,
+    height: u16,
+    floors: u8,
+    walls: HashSet<(u16, u16, u8)>,
+    walk: Ranges,
+    bd: Ranges,
+    transports: HashMap<(u16, u16, u8), Vec<Transport>>,
+    teleports: Vec<Teleport>,
+}
+
+impl GridMap {
+    pub(crate) fn new(width: u16, height: u16, floors: u8, walls: &[(u16, u16, u8)]) -> GridMap {
+        let mut map = GridMap {
+            width,
+            height,
+            floors,
+            walls: walls.iter().copied().collect(),
+            walk: HashMap::new(),
+            bd: HashMap::new(),
+            transports: HashMap::new(),
+            teleports: Vec::new(),
+        };
+        for floor in 0..floors {
+            for x in 0..width {
+                for y in 0..height {
+                    if map.is_open(x as i32, y as i32, floor) {
+                        let walk = map.reachable(x, y, floor, 2, true);
+                        let bd = map.reachable(x, y, floor, 10, false);
+                        map.walk.insert((x, y, floor), walk);
+                        map.bd.insert((x, y, floor), bd);
+                    }
+                }
+            }
+        }
+        map
+    }
+
+    pub(crate) fn with_transports(mut self, transports: Vec<Transport>) -> GridMap {
+        for transport in transports {
+            self.transports.entry((transport.from_x, transport.from_y, transport.from_floor)).or_default().push(transport);
+        }
+        self
+    }
+
+    pub(crate) fn with_teleports(mut self, teleports: Vec<Teleport>) -> GridMap {
+        self.teleports.extend(teleports);
+        self
+    }
+
+    fn is_open(&self, x: i32, y: i32, floor: u8) -> bool {
+        (0..self.width as i32).contains(&x) && (0..self.height as i32).contains(&y) && floor < self.floors
+            && !self.walls.contains(&(x as u16, y as u16, floor))
+    }
+
+    /// Open tiles within `range`, other than the tile itself. With `straight`, the tiles on the way must be
+    /// open too.
+    fn reachable(&self, x: u16, y: u16, floor: u8, range: i32, straight: bool) -> Vec<(u16, u16, u8)> {
+        let (x, y) = (x as i32, y as i32);
+        let mut tiles = Vec::new();
+        for dx in -range..=range {
+            for dy in -range..=range {
+                if (dx, dy) == (0, 0) || !self.is_open(x + dx, y + dy, floor) {
+                    continue;
+                }
+                let steps = dx.abs().max(dy.abs());
+                let clear = !straight || (1..steps).all(|step| self.is_open(x + dx * step / steps, y + dy * step / steps, floor));
+                if clear {
+                    tiles.push(((x + dx) as u16, (y + dy) as u16, direction(dx, dy)));
+                }
+            }
+        }
+        tiles
+    }
+
+    fn slide(&self, x: u16, y: u16, floor: u8, direction: u8, distance: i32) -> (usize, usize) {
+        let (dx, dy) = OFFSETS[direction as usize];
+        let (mut x, mut y) = (x as i32, y as i32);
+        for _ in 0..distance {
+            if !self.is_open(x + dx, y + dy, floor) {
+                break;
+            }
+            x += dx;
+            y += dy;
+        }
+        (x as usize, y as usize)
+    }
+}
+
+impl MapView for GridMap {
+    fn is_loaded(&self, x: u16, y: u16, floor: u8) -> bool {
+        x < self.width && y < self.height && floor < self.floors
+    }
+
+    fn walk_range(&self, x: u16, y: u16, floor: u8) -> &[(u16, u16, u8)] {
+        self.walk.get(&(x, y, floor)).map_or(&[], Vec::as_slice)
+    }
+
+    fn bd_range(&self, x: u16, y: u16, floor: u8) -> &[(u16, u16, u8)] {
+        self.bd.get(&(x, y, floor)).map_or(&[], Vec::as_slice)
+    }
+
+    fn surge_range(&self, x: u16, y: u16, floor: u8, direction: u8) -> (usize, usize) {
+        self.slide(x, y, floor, direction, 10)
+    }
+
+    fn escape_range(&self, x: u16, y: u16, floor: u8, direction: u8) -> (usize, usize) {
+        self.slide(x, y, floor, (direction + 4) % 8, 7)
+    }
+
+    fn transports(&self, x: u16, y: u16, floor: u8) -> &[Transport] {
+        self.transports.get(&(x, y, floor)).map_or(&[], Vec::as_slice)
+    }
+
+    fn all_transports(&self) -> impl Iterator<Item = &Transport> {
+        self.transports.values().flatten()
+    }
+
+    fn teleports(&self) -> &[Teleport] {
+        &self.teleports
+    }
+}
+
+/// A fresh directory under the system temp directory, removed again on drop.
+pub(crate) struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub(crate) fn new() -> TempDir {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = env::temp_dir().join(format!("rs3_pathfinding-{}-{}", process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Writes a collision file for every chunk under `paths`, blocked everywhere except for the `(x, y, floor,
+/// flags)` tiles of `open`.
+pub(crate) fn write_collision_data(paths: &DataPaths, open: &[(usize, usize, usize, u8)]) {
+    let compress = |data: &[u8]| {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    };
+    let blocked = compress(&vec![0; CHUNK_SIZE * CHUNK_SIZE]);
+    fs::create_dir_all(&paths.source_dir).unwrap();
+    for i in 0..RS_LENGTH / CHUNK_SIZE {
+        for j in 0..RS_HEIGHT / CHUNK_SIZE {
+            for k in 0..FLOORS {
+                let mut data = vec![0; CHUNK_SIZE * CHUNK_SIZE];
+                for &(x, y, _, flags) in open.iter().filter(|&&(x, y, floor, _)| (x / CHUNK_SIZE, y / CHUNK_SIZE, floor) == (i, j, k)) {
+                    // Collision data is stored with x varying fastest.
+                    data[(y % CHUNK_SIZE) * CHUNK_SIZE + x % CHUNK_SIZE] = flags;
+                }
+                let bytes = if data.iter().any(|&flags| flags != 0) { compress(&data) } else { blocked.clone() };
+                fs::write(paths.collision(i, j, k), bytes).unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use std::{collections::{HashMap, HashSet},
+          fs,
+          path::Path,
+          str::FromStr};
+use crate::error::{Error, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Requirement {
+    /// Skill names are lowercase, e.g. `agility`.
+    Level { skill: String, level: u8 },
+    Quest(String),
+}
+
+/// What a player has unlocked, for deciding which transports they can use.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Player {
+    pub levels: HashMap<String, u8>,
+    pub quests: HashSet<String>,
+}
+
+impl Player {
+    pub fn meets(&self, requirement: &Requirement) -> bool {
+        match requirement {
+            Requirement::Level { skill, level } => self.levels.get(skill).is_some_and(|l| l >= level),
+            Requirement::Quest(quest) => self.quests.contains(quest),
+        }
+    }
+}
+
+/// A one-way edge between two tiles that is not covered by the movement data, such as a staircase,
+/// door or agility shortcut. Taking it costs `ticks`, during which cooldowns keep ticking down.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Transport {
+    pub name: String,
+    pub from_x: u16,
+    pub from_y: u16,
+    pub from_floor: u8,
+    pub to_x: u16,
+    pub to_y: u16,
+    pub to_floor: u8,
+    pub ticks: usize,
+    pub requirements: Vec<Requirement>,
+}
+
+impl Transport {
+    pub fn new(from: (u16, u16, u8), to: (u16, u16, u8), ticks: usize) -> Transport {
+        Transport {
+            name: String::new(),
+            from_x: from.0,
+            from_y: from.1,
+            from_floor: from.2,
+            to_x: to.0,
+            to_y: to.1,
+            to_floor: to.2,
+            ticks,
+            requirements: Vec::new(),
+        }
+    }
+
+    /// Both directions of a connector that can be used either way, such as most stairs.
+    pub fn connector(a: (u16, u16, u8), b: (u16, u16, u8), ticks: usize) -> [Transport; 2] {
+        [Transport::new(a, b, ticks), Transport::new(b, a, ticks)]
+    }
+
+    fn reversed(&self) -> Transport {
+        Transport {
+            from_x: self.to_x,
+            from_y: self.to_y,
+            from_floor: self.to_floor,
+            to_x: self.from_x,
+            to_y: self.from_y,
+            to_floor: self.from_floor,
+            ..self.clone()
+        }
+    }
+
+    pub fn usable_by(&self, player: &Player) -> bool {
+        self.requirements.iter().all(|requirement| player.meets(requirement))
+    }
+}
+
+/// A teleport such as a lodestone, tablet or jewellery, usable from any tile.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Teleport {
+    pub name: String,
+    pub to_x: u16,
+    pub to_y: u16,
+    pub to_floor: u8,
+    pub ticks: usize,
+    pub requirements: Vec<Requirement>,
+}
+
+impl Teleport {
+    pub fn new(name: &str, to: (u16, u16, u8), ticks: usize) -> Teleport {
+        Teleport {
+            name: name.to_string(),
+            to_x: to.0,
+            to_y: to.1,
+            to_floor: to.2,
+            ticks,
+            requirements: Vec::new(),
+        }
+    }
+
+    pub fn usable_by(&self, player: &Player) -> bool {
+        self.requirements.iter().all(|requirement| player.meets(requirement))
+    }
+}
+
+/// Reads transport definitions, one per line:
+///
+/// `name,from_x,from_y,from_floor,to_x,to_y,to_floor,ticks,two_way,requirements`
+///
+/// `two_way` is `0` or `1`, and adds the reverse edge right after the forward one when set. `requirements` is
+/// a `;` separated list of `skill:level` or `quest:name` entries and may be empty. Blank lines and lines
+/// starting with `#` are skipped.
+pub fn load_transports(path: &Path) -> Result<Vec<Transport>> {
+    load_csv(path, 10, |fields, transports| {
+        let transport = Transport {
+            name: fields[0].to_string(),
+            from_x: parse_field(fields, 1)?,
+            from_y: parse_field(fields, 2)?,
+            from_floor: parse_field(fields, 3)?,
+            to_x: parse_field(fields, 4)?,
+            to_y: parse_field(fields, 5)?,
+            to_floor: parse_field(fields, 6)?,
+            ticks: parse_field(fields, 7)?,
+            requirements: parse_requirements(fields[9])?,
+        };
+        let two_way = match fields[8] {
+            "0" => false,
+            "1" => true,
+            other => return Err(format!("two_way must be 0 or 1, found {other:?}")),
+        };
+        let reversed = two_way.then(|| transport.reversed());
+        transports.push(transport);
+        transports.extend(reversed);
+        Ok(())
+    })
+}
+
+/// Reads teleport definitions in the same format as `load_transports`, one per line:
+///
+/// `name,to_x,to_y,to_floor,ticks,requirements`
+pub fn load_teleports(path: &Path) -> Result<Vec<Teleport>> {
+    load_csv(path, 6, |fields, teleports| {
+        teleports.push(Teleport {
+            name: fields[0].to_string(),
+            to_x: parse_field(fields, 1)?,
+            to_y: parse_field(fields, 2)?,
+            to_floor: parse_field(fields, 3)?,
+            ticks: parse_field(fields, 4)?,
+            requirements: parse_requirements(fields[5])?,
+        });
+        Ok(())
+    })
+}
+
+/// Splits each line of `path` that is not blank or a `#` comment into `field_count` trimmed fields and
+/// hands them to `parse_line`, turning its error message into `Error::Parse` for that line.
+fn load_csv<T>(path: &Path, field_count: usize, mut parse_line: impl FnMut(&[&str], &mut Vec<T>) -> std::result::Result<(), String>) -> Result<Vec<T>> {
+    let text = fs::read_to_string(path).map_err(|err| Error::io(path, err))?;
+    let mut items = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let parsed = if fields.len() == field_count {
+            parse_line(&fields, &mut items)
+        } else {
+            Err(format!("expected {field_count} fields, found {}", fields.len()))
+        };
+        parsed.map_err(|message| Error::Parse { path: path.to_path_buf(), line: i + 1, message })?;
+    }
+    Ok(items)
+}
+
+fn parse_field<T: FromStr>(fields: &[&str], i: usize) -> std::result::Result<T, String> {
+    fields[i].parse().map_err(|_| format!("field {} is not a valid number: {:?}", i + 1, fields[i]))
+}
+
+fn parse_requirements(field: &str) -> std::result::Result<Vec<Requirement>, String> {
+    field.split(';')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (kind, value) = entry.split_once(':').ok_or_else(|| format!("requirement {entry:?} is missing ':'"))?;
+            if kind.trim().eq_ignore_ascii_case("quest") {
+                Ok(Requirement::Quest(value.trim().to_string()))
+            } else {
+                let level = value.trim().parse().map_err(|_| format!("requirement {entry:?} has an invalid level"))?;
+                Ok(Requirement::Level { skill: kind.trim().to_lowercase(), level })
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_map::TempDir;
+
+    fn load(text: &str) -> Result<Vec<Transport>> {
+        let dir = TempDir::new();
+        let path = dir.path().join("transports.csv");
+        fs::write(&path, text).unwrap();
+        load_transports(&path)
+    }
+
+    #[test]
+    fn loads_one_and_two_way_transports_with_requirements() {
+        let transports = load("\
+# name,from_x,from_y,from_floor,to_x,to_y,to_floor,ticks,two_way,requirements
+Ladder,10,11,0,10,11,1,2,1,
+
+Wall jump, 20,21,0, 25,21,0, 4, 0, agility:50; quest:Plague City
+").unwrap();
+        assert_eq!(transports.len(), 3);
+        assert_eq!((transports[0].name.as_str(), transports[0].from_floor, transports[0].to_floor), ("Ladder", 0, 1));
+        assert_eq!(transports[1], transports[0].reversed());
+        assert_eq!(transports[2].requirements, [
+            Requirement::Level { skill: "agility".to_string(), level: 50 },
+            Requirement::Quest("Plague City".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn requirements_decide_who_can_use_a_transport() {
+        let transports = load("Wall jump,20,21,0,25,21,0,4,0,Agility:50;quest:Plague City\n").unwrap();
+        let mut player = Player::default();
+        player.levels.insert("agility".to_string(), 50);
+        assert!(!transports[0].usable_by(&player));
+        player.quests.insert("Plague City".to_string());
+        assert!(transports[0].usable_by(&player));
+        player.levels.insert("agility".to_string(), 49);
+        assert!(!transports[0].usable_by(&player));
+    }
+
+    #[test]
+    fn malformed_lines_report_their_line_number() {
+        let Err(Error::Parse { line, message, .. }) = load("Ladder,10,11,0,10,11,1,2,1,\nGate,1,2,0,3,4,0,x,0,\n") else {
+            panic!("expected a parse error");
+        };
+        assert_eq!(line, 2);
+        assert!(message.contains("field 8"), "{message}");
+        assert!(matches!(load("Gate,1,2,0,3,4,0,1,2,\n"), Err(Error::Parse { line: 1, .. })));
+        assert!(matches!(load("Gate,1,2,0,3,4,0,1,0,agility\n"), Err(Error::Parse { line: 1, .. })));
+    }
+
+    #[test]
+    fn loads_teleports() {
+        let dir = TempDir::new();
+        let path = dir.path().join("teleports.csv");
+        fs::write(&path, "Lumbridge lodestone,3233,3221,0,17,\nTrollheim teleport,2881,3669,0,5,magic:61;quest:Eadgar's Ruse\n").unwrap();
+        let teleports = load_teleports(&path).unwrap();
+        assert_eq!(teleports[0], Teleport::new("Lumbridge lodestone", (3233, 3221, 0), 17));
+        assert_eq!(teleports[1].requirements.len(), 2);
+        fs::write(&path, "Lumbridge lodestone,3233,3221,0,17\n").unwrap();
+        assert!(matches!(load_teleports(&path), Err(Error::Parse { line: 1, .. })));
+    }
+}
@@ -1,3 +1,9 @@
+/// World size in tiles along x and y.
+pub const RS_LENGTH: usize = 6400;
+pub const RS_HEIGHT: usize = 12800;
+pub const CHUNK_SIZE: usize = 1280;
+pub const FLOORS: usize = 4;
+
 pub fn free_direction(data: u8, direction: usize) -> bool{
     let t = [2, 32, 4, 64, 8, 128, 1, 16];
     data & t[direction] != 0
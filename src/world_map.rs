@@ -0,0 +1,164 @@
+use std::{collections::HashMap,
+          sync::{Arc, Mutex, OnceLock}};
+use crate::{chunk_cache::{ChunkCache, ChunkFormat, PAGE_SIZE},
+            data_paths::DataPaths,
+            error::Error,
+            map_section::{FloorData, MapView},
+            transport::{Teleport, Transport},
+            util::{FLOORS, RS_HEIGHT, RS_LENGTH}};
+const PAGES_X: usize = RS_LENGTH / PAGE_SIZE;
+const PAGES_Y: usize = RS_HEIGHT / PAGE_SIZE;
+
+/// The whole world, read from the generated chunk files the first time a search touches them and decoded
+/// one page at a time. Tiles whose chunk fails to load count as blocked; the errors are kept for
+/// `take_errors`.
+///
+/// Pages are decoded into the cache, so world maps built `with_cache` share them, and the cache evicts
+/// them within its budget. A map still holds on to every page it has used, since the ranges it hands out
+/// borrow from them, until it is dropped or `release_pages` is called. Make one per search, or release
+/// the pages between searches. A world map can be shared between threads.
+pub struct WorldMap {
+    cache: Arc<ChunkCache>,
+    pages: Vec<OnceLock<Option<Arc<FloorData>>>>,
+    errors: Mutex<Vec<Error>>,
+    transports: HashMap<(u16, u16, u8), Vec<Transport>>,
+    teleports: Vec<Teleport>,
+}
+
+impl WorldMap {
+    /// A world map with its own cache, which maps chunks and never evicts them or their pages.
+    pub fn new(paths: &DataPaths) -> WorldMap {
+        WorldMap::with_cache(Arc::new(ChunkCache::new(paths, usize::MAX, ChunkFormat::MappedNpy)))
+    }
+
+    pub fn with_cache(cache: Arc<ChunkCache>) -> WorldMap {
+        WorldMap {
+            cache,
+            pages: (0..PAGES_X * PAGES_Y * FLOORS).map(|_| OnceLock::new()).collect(),
+            errors: Mutex::new(Vec::new()),
+            transports: HashMap::new(),
+            teleports: Vec::new(),
+        }
+    }
+
+    pub fn add_transports<'a>(&mut self, transports: impl IntoIterator<Item = &'a Transport>) {
+        for transport in transports {
+            self.transports.entry((transport.from_x, transport.from_y, transport.from_floor))
+                .or_default()
+                .push(transport.clone());
+        }
+    }
+
+    pub fn add_teleports<'a>(&mut self, teleports: impl IntoIterator<Item = &'a Teleport>) {
+        self.teleports.extend(teleports.into_iter().cloned());
+    }
+
+    /// Errors from chunks that failed to load since the last call.
+    pub fn take_errors(&self) -> Vec<Error> {
+        std::mem::take(&mut self.errors.lock().unwrap())
+    }
+
+    /// Lets go of the pages the map has used so far, leaving them to the cache.
+    pub fn release_pages(&mut self) {
+        for page in &mut self.pages {
+            page.take();
+        }
+    }
+
+    fn page(&self, x: u16, y: u16, floor: u8) -> Option<&FloorData> {
+        let (x, y, floor) = (x as usize, y as usize, floor as usize);
+        if x >= RS_LENGTH || y >= RS_HEIGHT || floor >= FLOORS {
+            return None;
+        }
+        let (page_x, page_y) = (x / PAGE_SIZE, y / PAGE_SIZE);
+        self.pages[(floor * PAGES_Y + page_y) * PAGES_X + page_x]
+            .get_or_init(|| match self.cache.page(page_x, page_y, floor) {
+                Ok(page) => Some(page),
+                Err(err) => {
+                    self.errors.lock().unwrap().push(err);
+                    None
+                }
+            })
+            .as_deref()
+    }
+}
+
+impl MapView for WorldMap {
+    fn is_loaded(&self, x: u16, y: u16, floor: u8) -> bool {
+        self.page(x, y, floor).is_some()
+    }
+
+    fn walk_range(&self, x: u16, y: u16, floor: u8) -> &[(u16, u16, u8)] {
+        self.page(x, y, floor).map_or(&[], |page| page.walk_range(x, y))
+    }
+
+    fn bd_range(&self, x: u16, y: u16, floor: u8) -> &[(u16, u16, u8)] {
+        self.page(x, y, floor).map_or(&[], |page| page.bd_range(x, y))
+    }
+
+    fn surge_range(&self, x: u16, y: u16, floor: u8, direction: u8) -> (usize, usize) {
+        self.page(x, y, floor).map_or((x as usize, y as usize), |page| page.surge_range(x, y, direction))
+    }
+
+    fn escape_range(&self, x: u16, y: u16, floor: u8, direction: u8) -> (usize, usize) {
+        self.page(x, y, floor).map_or((x as usize, y as usize), |page| page.escape_range(x, y, direction))
+    }
+
+    fn transports(&self, x: u16, y: u16, floor: u8) -> &[Transport] {
+        self.transports.get(&(x, y, floor)).map_or(&[], Vec::as_slice)
+    }
+
+    fn all_transports(&self) -> impl Iterator<Item = &Transport> {
+        self.transports.values().flatten()
+    }
+
+    fn teleports(&self) -> &[Teleport] {
+        &self.teleports
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chunk_cache::tests::FakeSource,
+                util::CHUNK_SIZE};
+
+    #[test]
+    fn world_map_is_shareable_between_threads() {
+        fn assert_sync<T: Send + Sync>() {}
+        assert_sync::<WorldMap>();
+    }
+
+    #[test]
+    fn pages_are_cached_within_the_budget_and_held_by_their_map() {
+        // Chunks are bigger than the budget, so every page decoded loads its chunk again.
+        let source = Arc::new(FakeSource::new(CHUNK_SIZE));
+        let loads = || source.loads.lock().unwrap().values().sum::<usize>();
+        let page_size = {
+            let cache = ChunkCache::with_source(source.clone(), usize::MAX);
+            cache.page(0, 0, 0).unwrap().size()
+        };
+        let cache = Arc::new(ChunkCache::with_source(source.clone(), 3 * page_size));
+        let mut map = WorldMap::with_cache(cache.clone());
+        let step = PAGE_SIZE as u16;
+        for i in 0..5 {
+            assert!(map.walk_range(i * step, 0, 0).is_empty());
+        }
+        assert_eq!(loads(), 1 + 5);
+        assert_eq!(cache.bytes(), 3 * page_size);
+
+        // Another map shares the pages still cached, and decodes the evicted ones again.
+        let other = WorldMap::with_cache(cache.clone());
+        assert!(other.is_loaded(4 * step, 0, 0));
+        assert_eq!(loads(), 1 + 5);
+        assert!(other.is_loaded(0, 0, 0));
+        assert_eq!(loads(), 1 + 6);
+        // The first map keeps the pages it has used, whatever the cache evicts.
+        assert!(map.is_loaded(step, 0, 0));
+        assert_eq!(loads(), 1 + 6);
+        map.release_pages();
+        assert!(map.is_loaded(step, 0, 0));
+        assert_eq!(loads(), 1 + 7);
+        assert!(map.take_errors().is_empty());
+    }
+}